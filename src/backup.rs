@@ -0,0 +1,729 @@
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+use time::{format_description, format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+use indicatif::ProgressBar;
+use regex::Regex;
+
+use crate::{
+    im::IMUpdateConfig,
+    release::sanitize,
+    retention::select_prune,
+    settings::Settings,
+    utils::{format_time, get_spinner_style, glob_to_regex, list_files},
+};
+
+/// 备份中的一个文件
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FileEntry {
+    /// 相对于 Rime 用户目录的路径
+    pub path: String,
+    /// 文件内容的 SHA-256
+    pub hash: String,
+    pub size: u64,
+}
+
+/// 备份的原因
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupReason {
+    /// 更新前自动备份
+    #[default]
+    Update,
+    /// 通过 `backup create` 手动备份
+    Manual,
+    /// 由旧版本按目录保存的备份转换而来
+    Legacy,
+    /// 还原前自动备份，用于撤销还原
+    Restore,
+    /// 通过 `backup import` 从其它设备导入
+    Import,
+}
+
+/// 导出的压缩包中备份信息的文件名
+const EXPORT_MANIFEST: &str = "manifest.json";
+/// 导出的压缩包中文件所在的目录，与仓输入法的 Rime 目录压缩包一致
+const EXPORT_PREFIX: &str = "Rime/";
+
+/// 还原前的备份在该天数内不会被自动清理
+const RESTORE_SNAPSHOT_DAYS: i64 = 7;
+
+impl Display for BackupReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Update => write!(f, "更新前备份"),
+            Self::Manual => write!(f, "手动备份"),
+            Self::Legacy => write!(f, "旧版本备份"),
+            Self::Restore => write!(f, "还原前备份"),
+            Self::Import => write!(f, "导入的备份"),
+        }
+    }
+}
+
+/// 一次备份的清单，文件内容保存在备份仓库的 objects 目录下
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Snapshot {
+    /// 由备份时间及版本组成，如 20240412-083000-20240412
+    pub id: String,
+    /// 备份时的声笔版本
+    pub version: String,
+    /// 备份时间，RFC 3339 格式的 UTC 时间
+    #[serde(default)]
+    pub created_at: String,
+    /// 设备名称
+    #[serde(default)]
+    pub device: String,
+    #[serde(default)]
+    pub reason: BackupReason,
+    /// 手动备份时指定的说明
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// 所有文件的大小之和，单位字节
+    #[serde(default)]
+    pub size: u64,
+    /// 固定的备份不会被自动清理
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    pub files: Vec<FileEntry>,
+}
+
+impl Snapshot {
+    /// 备份时间，无法解析时视为最早的时间
+    pub fn created(&self) -> OffsetDateTime {
+        OffsetDateTime::parse(&self.created_at, &Rfc3339).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+    }
+
+    /// 备份时间，格式为 yyyy-MM-dd HH:mm:ss UTC
+    pub fn time(&self) -> String {
+        OffsetDateTime::parse(&self.created_at, &Rfc3339)
+            .map(|time| format_time(time.into()))
+            .unwrap_or_default()
+    }
+
+    /// 手动备份、导入的备份、固定的备份及最近的还原前备份不会被自动清理
+    pub fn is_protected(&self) -> bool {
+        let recent =
+            OffsetDateTime::now_utc() - self.created() < Duration::days(RESTORE_SNAPSHOT_DAYS);
+        self.pinned
+            || self.reason == BackupReason::Manual
+            || self.reason == BackupReason::Import
+            || (self.reason == BackupReason::Restore && recent)
+    }
+
+    /// 只保留路径与任意一个通配符匹配的文件
+    pub fn filter(&self, patterns: &[String]) -> Result<Snapshot, regex::Error> {
+        let regexes = patterns
+            .iter()
+            .map(|x| glob_to_regex(x))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut snapshot = self.clone();
+        snapshot
+            .files
+            .retain(|x| regexes.iter().any(|regex| regex.is_match(&x.path)));
+        snapshot.size = snapshot.files.iter().map(|x| x.size).sum();
+        Ok(snapshot)
+    }
+
+    /// 用于选择备份时显示的说明
+    pub fn description(&self) -> String {
+        let mut description = format!("{}  {}  {}", self.id, self.version, self.reason);
+        if let Some(label) = &self.label {
+            description.push_str(&format!("：{label}"));
+        }
+        if self.pinned {
+            description.push_str("（已固定）");
+        }
+        description
+    }
+}
+
+/// 按内容寻址的备份仓库，相同内容的文件在所有备份中只保存一份
+///
+/// - objects/ab/cdef...: 文件内容，以 SHA-256 命名，开启压缩时为 gzip 格式并带有 .gz 后缀
+/// - snapshots/<id>.json: 备份清单
+pub struct BackupStore {
+    root: PathBuf,
+    device: String,
+    compress: bool,
+    exclude: Vec<Regex>,
+}
+
+impl BackupStore {
+    /// 打开设备的备份仓库，旧版本按目录保存的备份会被转换到仓库中
+    pub fn open(config: &IMUpdateConfig) -> io::Result<Self> {
+        let store = Self::open_read_only(config)?;
+        fs::create_dir_all(store.objects_dir())?;
+        fs::create_dir_all(store.snapshots_dir())?;
+        store.migrate_legacy()?;
+        Ok(store)
+    }
+
+    /// 只读地打开备份仓库，不创建目录也不转换旧版本的备份，用于 --dry-run 等不应修改磁盘的场合
+    pub fn open_read_only(config: &IMUpdateConfig) -> io::Result<Self> {
+        let settings = &Settings::current().backup;
+        let mut exclude = vec![];
+        for pattern in settings.exclude_patterns() {
+            let regex = glob_to_regex(pattern).map_err(|error| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("备份排除规则 {pattern} 无效: {error}"),
+                )
+            })?;
+            exclude.push(regex);
+        }
+
+        Ok(Self {
+            root: config.backup_dir(),
+            device: config.device(),
+            compress: settings.compress,
+            exclude,
+        })
+    }
+
+    /// 文件是否被排除在备份之外
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.exclude.iter().any(|regex| regex.is_match(path))
+    }
+
+    fn objects_dir(&self) -> PathBuf {
+        self.root.join("objects")
+    }
+
+    fn snapshots_dir(&self) -> PathBuf {
+        self.root.join("snapshots")
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.objects_dir().join(&hash[..2]).join(&hash[2..])
+    }
+
+    fn manifest_path(&self, id: &str) -> PathBuf {
+        self.snapshots_dir().join(format!("{id}.json"))
+    }
+
+    /// 旧版本的备份是 backups/<版本> 目录，仓输入法的备份是其中的 Rime.zip
+    fn migrate_legacy(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.root)?.filter_map(Result::ok) {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !path.is_dir() || name == "objects" || name == "snapshots" {
+                continue;
+            }
+
+            println!("转换旧备份 {name}");
+            let created_at = entry
+                .metadata()
+                .and_then(|x| x.modified())
+                .map(OffsetDateTime::from)
+                .unwrap_or_else(|_| OffsetDateTime::now_utc())
+                .replace_nanosecond(0)
+                .unwrap();
+            let snapshot = Snapshot {
+                id: name.clone(),
+                version: name.clone(),
+                created_at: created_at.format(&Rfc3339).unwrap_or_default(),
+                device: self.device.clone(),
+                reason: BackupReason::Legacy,
+                label: None,
+                size: 0,
+                pinned: false,
+                files: vec![],
+            };
+            let zip_path = path.join("Rime.zip");
+            if zip_path.exists() {
+                self.create_from_zip(snapshot, &zip_path, "Rime/")?;
+            } else {
+                self.create_from_dir(snapshot, &path, |_| {})?;
+            }
+            fs::remove_dir_all(path)?;
+        }
+        Ok(())
+    }
+
+    /// 所有备份，按备份时间排序
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        let mut snapshots: Vec<Snapshot> = fs::read_dir(self.snapshots_dir())
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|content| serde_json::from_str(&content).ok())
+            .collect();
+        snapshots.sort_by(|a, b| (&a.created_at, &a.id).cmp(&(&b.created_at, &b.id)));
+        snapshots
+    }
+
+    pub fn get(&self, id: &str) -> io::Result<Snapshot> {
+        let content =
+            fs::read_to_string(self.manifest_path(id)).map_err(|error| match error.kind() {
+                io::ErrorKind::NotFound => {
+                    io::Error::new(io::ErrorKind::NotFound, format!("备份 {id} 不存在"))
+                }
+                _ => error,
+            })?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn exists(&self, id: &str) -> bool {
+        self.manifest_path(id).exists()
+    }
+
+    /// 与已有备份重复时在 `id` 后加上序号
+    fn unique_id(&self, id: &str) -> String {
+        let mut unique = id.to_string();
+        let mut n = 1;
+        while self.exists(&unique) {
+            n += 1;
+            unique = format!("{id}-{n}");
+        }
+        unique
+    }
+
+    /// 新备份的清单，id 由当前时间及版本组成，文件列表为空
+    pub fn new_snapshot(
+        &self,
+        version: &str,
+        reason: BackupReason,
+        label: Option<String>,
+    ) -> Snapshot {
+        let now = OffsetDateTime::now_utc().replace_nanosecond(0).unwrap();
+        let format =
+            format_description::parse("[year][month][day]-[hour][minute][second]").unwrap();
+        let id = format!("{}-{}", now.format(&format).unwrap(), sanitize(version));
+
+        Snapshot {
+            id: self.unique_id(&id),
+            version: version.to_string(),
+            created_at: now.format(&Rfc3339).unwrap(),
+            device: self.device.clone(),
+            reason,
+            label,
+            size: 0,
+            pinned: false,
+            files: vec![],
+        }
+    }
+
+    /// 保存文件内容，已存在相同内容时直接复用
+    fn store_object(&self, reader: &mut impl Read) -> io::Result<(String, u64)> {
+        let temp_path = self
+            .objects_dir()
+            .join(format!("tmp-{}", std::process::id()));
+        let mut temp_file = File::create(&temp_path)?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut buffer = [0; 64 * 1024];
+        {
+            let mut writer: Box<dyn Write> = if self.compress {
+                Box::new(GzEncoder::new(&mut temp_file, Compression::default()))
+            } else {
+                Box::new(&mut temp_file)
+            };
+            loop {
+                let len = reader.read(&mut buffer)?;
+                if len == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..len]);
+                writer.write_all(&buffer[..len])?;
+                size += len as u64;
+            }
+            writer.flush()?;
+        }
+        drop(temp_file);
+
+        let hash = format!("{:x}", hasher.finalize());
+        let object_path = self.object_path(&hash);
+        let compressed_path = object_path.with_extension("gz");
+        if object_path.exists() || compressed_path.exists() {
+            fs::remove_file(temp_path)?;
+        } else {
+            fs::create_dir_all(object_path.parent().unwrap())?;
+            let target = if self.compress {
+                compressed_path
+            } else {
+                object_path
+            };
+            fs::rename(temp_path, target)?;
+        }
+
+        Ok((hash, size))
+    }
+
+    /// 读取文件内容
+    pub fn open_object(&self, hash: &str) -> io::Result<Box<dyn Read>> {
+        let object_path = self.object_path(hash);
+        let compressed_path = object_path.with_extension("gz");
+        if compressed_path.exists() {
+            Ok(Box::new(GzDecoder::new(File::open(compressed_path)?)))
+        } else {
+            Ok(Box::new(File::open(object_path)?))
+        }
+    }
+
+    fn write_manifest(&self, snapshot: &Snapshot) -> io::Result<()> {
+        let content = serde_json::to_string_pretty(snapshot)?;
+        fs::write(self.manifest_path(&snapshot.id), content)
+    }
+
+    /// 将目录中的所有文件保存到备份中
+    pub fn create_from_dir<F>(
+        &self,
+        mut snapshot: Snapshot,
+        dir: &Path,
+        callback: F,
+    ) -> io::Result<Snapshot>
+    where
+        F: Fn(&str),
+    {
+        let mut files = vec![];
+        for path in list_files(dir) {
+            if self.is_excluded(&path) {
+                continue;
+            }
+
+            callback(&path);
+            let mut file = File::open(dir.join(&path))?;
+            let (hash, size) = self.store_object(&mut file)?;
+            files.push(FileEntry { path, hash, size });
+        }
+
+        snapshot.size = files.iter().map(|x| x.size).sum();
+        snapshot.files = files;
+        self.write_manifest(&snapshot)?;
+        Ok(snapshot)
+    }
+
+    /// 将压缩包中 `prefix` 下的所有文件保存到备份中，其它文件被忽略
+    pub fn create_from_zip(
+        &self,
+        mut snapshot: Snapshot,
+        zip_path: &Path,
+        prefix: &str,
+    ) -> io::Result<Snapshot> {
+        let mut archive = ZipArchive::new(File::open(zip_path)?)?;
+        let mut files = vec![];
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            if file.is_dir() {
+                continue;
+            }
+
+            let Some(name) = file.enclosed_name() else {
+                continue;
+            };
+            let name = name.to_string_lossy().replace('\\', "/");
+            let Some(path) = name.strip_prefix(prefix).map(str::to_string) else {
+                continue;
+            };
+            if self.is_excluded(&path) {
+                continue;
+            }
+
+            let (hash, size) = self.store_object(&mut file)?;
+            files.push(FileEntry { path, hash, size });
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        snapshot.size = files.iter().map(|x| x.size).sum();
+        snapshot.files = files;
+        self.write_manifest(&snapshot)?;
+        Ok(snapshot)
+    }
+
+    /// 将备份导出为压缩包，其中的 manifest.json 为备份的信息，文件位于 Rime/ 下
+    pub fn export(&self, snapshot: &Snapshot, output: &Path) -> io::Result<()> {
+        let mut writer = ZipWriter::new(File::create(output)?);
+        let options = SimpleFileOptions::default();
+        writer.start_file(EXPORT_MANIFEST, options)?;
+        writer.write_all(serde_json::to_string_pretty(snapshot)?.as_bytes())?;
+
+        for entry in &snapshot.files {
+            writer.start_file(format!("{EXPORT_PREFIX}{}", entry.path), options)?;
+            io::copy(&mut self.open_object(&entry.hash)?, &mut writer)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// 导入由 `export` 导出的压缩包，保留原备份的时间、版本、设备及说明
+    pub fn import(&self, zip_path: &Path) -> io::Result<Snapshot> {
+        let mut archive = ZipArchive::new(File::open(zip_path)?)?;
+        let manifest: Snapshot = match archive.by_name(EXPORT_MANIFEST) {
+            Ok(file) => serde_json::from_reader(file)?,
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} 中没有 {EXPORT_MANIFEST}，不是导出的备份",
+                        zip_path.display()
+                    ),
+                ))
+            }
+        };
+
+        // id 来自压缩包，用作文件名前去掉路径分隔符等字符，无法使用时按当前时间重新生成
+        let id = sanitize(&manifest.id);
+        let id = if id.trim_matches('.').is_empty() {
+            self.new_snapshot(&manifest.version, BackupReason::Import, None)
+                .id
+        } else {
+            self.unique_id(&id)
+        };
+        let snapshot = Snapshot {
+            id,
+            reason: BackupReason::Import,
+            pinned: false,
+            files: vec![],
+            ..manifest
+        };
+        self.create_from_zip(snapshot, zip_path, EXPORT_PREFIX)
+    }
+
+    /// 按备份清单在 `target` 中重建文件
+    pub fn restore_to_dir<F>(
+        &self,
+        snapshot: &Snapshot,
+        target: &Path,
+        callback: F,
+    ) -> io::Result<()>
+    where
+        F: Fn(&str),
+    {
+        for entry in &snapshot.files {
+            callback(&entry.path);
+            let path = target.join(&entry.path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let mut reader = self.open_object(&entry.hash)?;
+            let mut file = File::create(path)?;
+            io::copy(&mut reader, &mut file)?;
+        }
+        Ok(())
+    }
+
+    /// 删除备份，并清理不再被任何备份引用的文件
+    pub fn delete(&self, id: &str) -> io::Result<()> {
+        let snapshot = self.get(id)?;
+        fs::remove_file(self.manifest_path(&snapshot.id))?;
+        self.gc()
+    }
+
+    /// 检查备份中的文件内容是否完整，返回发现的问题
+    pub fn verify<F>(&self, snapshot: &Snapshot, callback: F) -> Vec<String>
+    where
+        F: Fn(&str),
+    {
+        let mut problems = vec![];
+        for entry in &snapshot.files {
+            callback(&entry.path);
+            let mut reader = match self.open_object(&entry.hash) {
+                Ok(reader) => reader,
+                Err(_) => {
+                    problems.push(format!("{}: 文件内容缺失", entry.path));
+                    continue;
+                }
+            };
+
+            let mut hasher = Sha256::new();
+            match io::copy(&mut reader, &mut hasher) {
+                Ok(size)
+                    if size == entry.size && format!("{:x}", hasher.finalize()) == entry.hash => {}
+                Ok(_) => problems.push(format!("{}: 文件内容与记录不一致", entry.path)),
+                Err(error) => problems.push(format!("{}: 读取失败：{error}", entry.path)),
+            }
+        }
+        problems
+    }
+
+    /// 备份仓库中所有文件实际占用的空间
+    pub fn disk_usage(&self) -> u64 {
+        list_files(&self.objects_dir())
+            .iter()
+            .filter_map(|path| fs::metadata(self.objects_dir().join(path)).ok())
+            .map(|x| x.len())
+            .sum()
+    }
+
+    /// 清理不再被任何备份引用的文件
+    pub fn gc(&self) -> io::Result<()> {
+        let referenced: HashSet<String> = self
+            .snapshots()
+            .into_iter()
+            .flat_map(|snapshot| snapshot.files.into_iter().map(|x| x.hash))
+            .collect();
+
+        for dir in fs::read_dir(self.objects_dir())?.filter_map(Result::ok) {
+            if !dir.path().is_dir() {
+                continue;
+            }
+
+            let prefix = dir.file_name().to_string_lossy().to_string();
+            for object in fs::read_dir(dir.path())?.filter_map(Result::ok) {
+                let name = object.file_name().to_string_lossy().to_string();
+                let hash = format!("{prefix}{}", name.trim_end_matches(".gz"));
+                if !referenced.contains(&hash) {
+                    fs::remove_file(object.path())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 固定或取消固定备份
+    pub fn set_pinned(&self, id: &str, pinned: bool) -> io::Result<()> {
+        let mut snapshot = self.get(id)?;
+        snapshot.pinned = pinned;
+        self.write_manifest(&snapshot)
+    }
+
+    /// 按设备的保留策略需要删除的备份，`pending` 为即将创建的备份
+    pub fn to_prune(&self, config: &IMUpdateConfig, pending: Option<Snapshot>) -> Vec<Snapshot> {
+        let mut snapshots = self.snapshots();
+        snapshots.extend(pending);
+        select_prune(
+            &snapshots,
+            config.max_backups,
+            &config.retention,
+            OffsetDateTime::now_utc(),
+        )
+    }
+
+    /// 按设备的保留策略删除备份，返回被删除的备份
+    pub fn prune(&self, config: &IMUpdateConfig) -> io::Result<Vec<Snapshot>> {
+        let snapshots = self.to_prune(config, None);
+        for snapshot in &snapshots {
+            fs::remove_file(self.manifest_path(&snapshot.id))?;
+        }
+        if !snapshots.is_empty() {
+            self.gc()?;
+        }
+        Ok(snapshots)
+    }
+}
+
+/// 打开备份仓库，更新前的自动备份在未开启备份时返回 None
+pub fn prepare_backup(config: &IMUpdateConfig, reason: BackupReason) -> Option<BackupStore> {
+    if reason == BackupReason::Update && config.max_backups == 0 {
+        return None;
+    }
+
+    let store = match BackupStore::open(config) {
+        Ok(store) => store,
+        Err(error) => {
+            println!("打开备份目录失败：{error}");
+            return None;
+        }
+    };
+
+    Some(store)
+}
+
+/// 备份完成后，更新前的自动备份按保留策略清理旧的备份
+pub fn finish_backup(store: &BackupStore, config: &IMUpdateConfig, snapshot: &Snapshot) {
+    if snapshot.reason != BackupReason::Update {
+        return;
+    }
+
+    match store.prune(config) {
+        Ok(snapshots) => {
+            for snapshot in snapshots {
+                println!("删除旧备份 {}", snapshot.description());
+            }
+        }
+        Err(error) => println!("清理旧备份失败：{error}"),
+    }
+}
+
+/// 备份本地输入法的 Rime 用户目录，返回创建的备份
+pub fn backup_user_dir(
+    config: &IMUpdateConfig,
+    reason: BackupReason,
+    label: Option<String>,
+) -> Option<Snapshot> {
+    let store = prepare_backup(config, reason)?;
+
+    println!("备份当前版本到：{}", config.backup_dir().display());
+    let pb = ProgressBar::new_spinner();
+    pb.set_prefix(format!("备份当前版本 {}", &config.version));
+    pb.set_style(get_spinner_style());
+    let snapshot = store.new_snapshot(&config.version, reason, label);
+    match store.create_from_dir(snapshot, &config.user_dir, |path| {
+        pb.set_message(path.to_string());
+        pb.inc(1);
+    }) {
+        Ok(snapshot) => {
+            pb.finish_with_message(format!("完成 {}", snapshot.id));
+            finish_backup(&store, config, &snapshot);
+            Some(snapshot)
+        }
+        Err(error) => {
+            pb.finish_and_clear();
+            println!("备份当前版本失败：{error}");
+            None
+        }
+    }
+}
+
+/// 用备份替换本地输入法的 Rime 用户目录中的文件，`partial` 为 true 时只覆盖备份中的文件
+pub fn restore_user_dir(config: &IMUpdateConfig, snapshot: &Snapshot, partial: bool) {
+    let store = match BackupStore::open(config) {
+        Ok(store) => store,
+        Err(error) => {
+            println!("打开备份目录失败：{error}");
+            return;
+        }
+    };
+
+    // 删除用户目录中的文件前先确认备份完整，以免还原失败时丢失现有的文件
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(get_spinner_style());
+    pb.set_prefix("检查备份");
+    let problems = store.verify(snapshot, |path| {
+        pb.set_message(path.to_string());
+        pb.inc(1);
+    });
+    pb.finish_and_clear();
+    if !problems.is_empty() {
+        println!("备份 {} 不完整，没有还原：", snapshot.id);
+        for problem in problems {
+            println!("  {problem}");
+        }
+        return;
+    }
+
+    // 完整还原时删除现有的文件，只保留被排除在备份之外的用户词库等，部分还原时保留所有文件
+    let to = &config.user_dir;
+    if !partial {
+        for path in list_files(to) {
+            if store.is_excluded(&path) {
+                continue;
+            }
+            if let Err(error) = fs::remove_file(to.join(&path)) {
+                println!("删除 {path} 失败：{error}");
+            }
+        }
+    }
+
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(get_spinner_style());
+    pb.set_prefix("还原");
+    if let Err(error) = store.restore_to_dir(snapshot, to, |path| {
+        pb.set_message(path.to_string());
+        pb.inc(1);
+    }) {
+        println!("还原失败：{error}")
+    }
+    pb.finish_with_message("完成");
+}
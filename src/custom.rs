@@ -0,0 +1,201 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use console::style;
+
+use crate::{
+    diff::unified_diff,
+    im::IMUpdateConfig,
+    options::{apply_options, PatchFile, PATCH_FILE},
+    utils::work_dir,
+};
+
+/// 补丁集所在的目录名称，以 _ 开头以免被当作设备
+const CUSTOM_DIR: &str = "_custom";
+/// 补丁文件的扩展名
+const PATCH_EXTENSION: &str = ".custom.yaml";
+
+/// 补丁集所在目录，每个补丁集是其下的一个子目录
+pub fn custom_dir() -> PathBuf {
+    work_dir().join(CUSTOM_DIR)
+}
+
+fn check_name(name: &str) -> io::Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("补丁集名称 {name} 无效，只能包含字母、数字、- 及 _"),
+        ));
+    }
+    Ok(())
+}
+
+/// 所有补丁集的名称
+pub fn patch_sets() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(custom_dir())
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
+
+/// 补丁集中的文件，键为文件名，如 sbsrf.custom.yaml
+pub fn patch_files(name: &str) -> io::Result<BTreeMap<String, PathBuf>> {
+    check_name(name)?;
+    let dir = custom_dir().join(name);
+    if !dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("补丁集 {name} 不存在"),
+        ));
+    }
+
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name.ends_with(PATCH_EXTENSION) && entry.path().is_file() {
+            files.insert(file_name, entry.path());
+        }
+    }
+    Ok(files)
+}
+
+/// 将文件保存到补丁集中，补丁集不存在时创建，同名文件被覆盖
+pub fn save_patch_set(name: &str, files: &[PathBuf]) -> io::Result<Vec<String>> {
+    check_name(name)?;
+    for file in files {
+        let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+        if !file_name.ends_with(PATCH_EXTENSION) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} 不是 *{PATCH_EXTENSION} 补丁文件", file.display()),
+            ));
+        }
+    }
+
+    let dir = custom_dir().join(name);
+    fs::create_dir_all(&dir)?;
+    let mut saved = vec![];
+    for file in files {
+        let file_name = file.file_name().unwrap_or_default();
+        fs::copy(file, dir.join(file_name))?;
+        saved.push(file_name.to_string_lossy().to_string());
+    }
+    Ok(saved)
+}
+
+pub fn remove_patch_set(name: &str) -> io::Result<()> {
+    check_name(name)?;
+    fs::remove_dir_all(custom_dir().join(name))
+}
+
+/// 设备启用的所有补丁文件，多个补丁集中有同名文件时以后启用的为准
+pub fn device_patches(config: &IMUpdateConfig) -> io::Result<BTreeMap<String, PathBuf>> {
+    let mut patches = BTreeMap::new();
+    for name in &config.custom {
+        patches.extend(patch_files(name)?);
+    }
+    Ok(patches)
+}
+
+/// 设备上 sbsrf.custom.yaml 应有的内容，以补丁集中的该文件为基础，再写入设备配置中的选项
+///
+/// 补丁集中没有该文件时以 `dir` 中现有的为基础，设备配置中未设置的选项保留原有的值
+pub fn options_patch(config: &IMUpdateConfig, dir: &Path) -> io::Result<PatchFile> {
+    let mut patch = match device_patches(config)?.get(PATCH_FILE) {
+        Some(path) => PatchFile::parse(&fs::read_to_string(path)?),
+        None => PatchFile::read(dir)?,
+    };
+    apply_options(&mut patch, config);
+    Ok(patch)
+}
+
+/// 将设备启用的补丁复制到 `target` 中，sbsrf.custom.yaml 同时写入设备配置中的选项，返回写入的文件名
+pub fn apply_patches(config: &IMUpdateConfig, target: &Path) -> io::Result<Vec<String>> {
+    fs::create_dir_all(target)?;
+    let mut applied = vec![];
+    let patches = device_patches(config)?;
+    for (file_name, path) in &patches {
+        if file_name != PATCH_FILE {
+            fs::copy(path, target.join(file_name))?;
+            applied.push(file_name.clone());
+        }
+    }
+
+    // 补丁集及 `target` 中都没有 sbsrf.custom.yaml 时不创建
+    let current = fs::read_to_string(target.join(PATCH_FILE)).ok();
+    if patches.contains_key(PATCH_FILE) || current.is_some() {
+        let patch = options_patch(config, target)?;
+        if current.as_deref() != Some(patch.content().as_str()) {
+            patch.write(target)?;
+            applied.push(PATCH_FILE.to_string());
+        }
+    }
+    applied.sort();
+    Ok(applied)
+}
+
+/// 更新后将启用的补丁及设备配置中的选项写入用户目录，在重新部署前调用
+pub fn apply_to_user_dir(config: &IMUpdateConfig) {
+    match apply_patches(config, &config.user_dir) {
+        Ok(files) if files.is_empty() => {}
+        Ok(files) => println!("已应用自定义补丁：{}", files.join("、")),
+        Err(error) => println!("应用自定义补丁失败：{error}"),
+    }
+}
+
+/// 比较启用的补丁与 `dir` 中的文件，显示缺失或被修改的补丁，存在差异时返回 true
+pub fn print_drift(config: &IMUpdateConfig, dir: &Path) -> io::Result<bool> {
+    let patches = device_patches(config)?;
+    if patches.is_empty() {
+        println!("设备没有启用任何补丁集");
+        return Ok(false);
+    }
+
+    let mut drifted = false;
+    for (file_name, path) in patches {
+        let expected = if file_name == PATCH_FILE {
+            options_patch(config, dir)?.content()
+        } else {
+            fs::read_to_string(&path)?
+        };
+        let actual = match fs::read_to_string(dir.join(&file_name)) {
+            Ok(actual) => actual,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                drifted = true;
+                println!("  {} {file_name}：设备上没有该文件", style("-").red());
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+
+        if actual == expected {
+            println!("  {} {file_name}", style("=").green());
+            continue;
+        }
+
+        drifted = true;
+        println!("  {} {file_name}：与保存的补丁不一致", style("~").yellow());
+        for line in unified_diff(&expected, &actual).unwrap_or_default() {
+            match line.chars().next() {
+                Some('@') => println!("    {}", style(line).cyan()),
+                Some('+') => println!("    {}", style(line).green()),
+                Some('-') => println!("    {}", style(line).red()),
+                _ => println!("    {line}"),
+            }
+        }
+    }
+    Ok(drifted)
+}
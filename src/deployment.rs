@@ -0,0 +1,284 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, fs,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use console::style;
+use indicatif::ProgressBar;
+use regex::Regex;
+
+use crate::{im::IMUpdateConfig, utils::get_spinner_style, validate::enabled_schemas};
+
+/// 等待部署完成的最长时间，编译声笔的词典需要一些时间
+const DEPLOY_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// 输入法的 Rime 日志文件名的前缀，日志位于系统的临时目录中
+fn log_prefix(config: &IMUpdateConfig) -> Option<&'static str> {
+    match config.name.as_str() {
+        "Squirrel" => Some("rime.squirrel"),
+        "Fcitx5" => Some("rime.fcitx-rime"),
+        "Weasel" => Some("rime.weasel"),
+        _ => None,
+    }
+}
+
+/// 临时目录中输入法的日志文件，新版本的小狼毫将日志放在 rime.weasel 目录中
+fn log_files(prefix: &str) -> Vec<PathBuf> {
+    let mut files = vec![];
+    let temp_dir = env::temp_dir();
+    for entry in fs::read_dir(&temp_dir).into_iter().flatten().flatten() {
+        if !entry.file_name().to_string_lossy().starts_with(prefix) {
+            continue;
+        }
+
+        // glog 为最新的日志创建的符号链接与日志文件内容相同，跳过
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_file() {
+            files.push(entry.path());
+        } else if file_type.is_dir() {
+            for entry in fs::read_dir(entry.path()).into_iter().flatten().flatten() {
+                if entry.file_type().is_ok_and(|x| x.is_file()) {
+                    files.push(entry.path());
+                }
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+/// 部署的检查结果
+#[derive(Debug, Default)]
+pub struct DeployReport {
+    /// 日志中的错误，能找到对应的配置文件时以 文件:行号 开头
+    pub errors: Vec<String>,
+    /// 没有重新生成的 build 目录中的方案
+    pub stale: Vec<String>,
+}
+
+impl DeployReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty() && self.stale.is_empty()
+    }
+
+    /// 显示检查结果，返回部署是否成功
+    pub fn print_result(&self) -> bool {
+        if self.is_ok() {
+            println!("{}", style("部署成功").green());
+            return true;
+        }
+
+        println!("{}", style("部署失败").red());
+        for error in &self.errors {
+            println!("  {} {error}", style("x").red());
+        }
+        for schema in &self.stale {
+            println!(
+                "  {} build/{schema} 没有重新生成，部署可能未完成或出错",
+                style("x").red()
+            );
+        }
+        false
+    }
+}
+
+/// 在部署前记录日志的位置，部署后检查新增的日志及 build 目录中的方案
+pub struct DeployWatch {
+    prefix: Option<&'static str>,
+    /// 部署前各日志文件的大小，只检查之后新增的内容
+    offsets: HashMap<PathBuf, u64>,
+}
+
+impl DeployWatch {
+    pub fn start(config: &IMUpdateConfig) -> Self {
+        let prefix = log_prefix(config);
+        let offsets = prefix
+            .map(log_files)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|path| Some((path.clone(), fs::metadata(&path).ok()?.len())))
+            .collect();
+        Self { prefix, offsets }
+    }
+
+    /// 等待 build 目录中的方案重新生成，超时或日志中出现错误时停止等待
+    pub async fn wait(&self, config: &IMUpdateConfig) -> DeployReport {
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(get_spinner_style());
+        pb.set_prefix("检查部署结果");
+
+        let schemas = enabled_schemas(config);
+        let started = SystemTime::now();
+        let mut report = DeployReport::default();
+        loop {
+            pb.tick();
+            report.errors = self.errors();
+            report.stale = stale_schemas(&config.user_dir, &schemas);
+            if report.stale.is_empty()
+                || !report.errors.is_empty()
+                || started.elapsed().unwrap_or_default() > DEPLOY_TIMEOUT
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        pb.finish_and_clear();
+        report
+    }
+
+    /// 部署后日志中新增的错误，同一错误在多个日志文件中出现时只保留一次
+    fn errors(&self) -> Vec<String> {
+        let Some(prefix) = self.prefix else {
+            return vec![];
+        };
+
+        let mut errors: Vec<String> = vec![];
+        for path in log_files(prefix) {
+            let offset = self.offsets.get(&path).copied().unwrap_or_default();
+            let Ok(mut file) = fs::File::open(&path) else {
+                continue;
+            };
+            let mut content = vec![];
+            if file.seek(SeekFrom::Start(offset)).is_err()
+                || file.read_to_end(&mut content).is_err()
+            {
+                continue;
+            }
+
+            for error in parse_errors(&String::from_utf8_lossy(&content)) {
+                if !errors.contains(&error) {
+                    errors.push(error);
+                }
+            }
+        }
+        errors
+    }
+}
+
+/// 解析 glog 格式的日志，如 `E20240412 08:30:00.123456  1234 config_data.cc:66] ...`
+///
+/// YAML 的错误中只有行号，文件取自之前最近一次加载的配置文件
+fn parse_errors(log: &str) -> Vec<String> {
+    let line_regex = Regex::new(r"^([IWEF])\d{4,8} [\d:.]+\s+\d+ [^\]]+\] (.*)$").unwrap();
+    let file_regex = Regex::new(r"([^\s'\x22]+\.ya?ml)").unwrap();
+    let position_regex = Regex::new(r"line (\d+)").unwrap();
+
+    let mut errors = vec![];
+    let mut current_file: Option<String> = None;
+    for line in log.lines() {
+        let Some(captures) = line_regex.captures(line) else {
+            continue;
+        };
+        let message = captures[2].to_string();
+        if let Some(file) = file_regex.captures(&message) {
+            current_file = Some(file[1].to_string());
+        }
+        if &captures[1] != "E" && &captures[1] != "F" {
+            continue;
+        }
+
+        let position = position_regex.captures(&message).map(|x| x[1].to_string());
+        let error = match (&current_file, position) {
+            (Some(file), Some(line)) => format!("{file}:{line}: {message}"),
+            (Some(file), None) if message.contains(file.as_str()) => message,
+            (Some(file), None) => format!("{file}: {message}"),
+            (None, _) => message,
+        };
+        errors.push(error);
+    }
+    errors
+}
+
+/// 启用的方案中，用户目录中的方案在 build 目录中的编译结果比方案或其补丁旧，或者不存在
+///
+/// 不在 schema_list 中且不被依赖的方案部署时不会编译，不检查
+fn stale_schemas(user_dir: &Path, schemas: &BTreeSet<String>) -> Vec<String> {
+    let mut stale = vec![];
+    for schema_id in schemas {
+        let name = format!("{schema_id}.schema.yaml");
+        let Some(schema) = modified(&user_dir.join(&name)) else {
+            continue;
+        };
+
+        let source = [
+            Some(schema),
+            modified(&user_dir.join(format!("{schema_id}.custom.yaml"))),
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+        let built = modified(&user_dir.join("build").join(&name));
+        if built.is_none() || built < source {
+            stale.push(name);
+        }
+    }
+    stale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 上一次部署留下的日志，开始监视时记录的偏移量位于其末尾
+    const BEFORE: &str = "Log file created at: 2024/04/12 08:00:00
+Running on machine: macbook
+Running duration (h:mm:ss): 0:00:00
+Log line format: [IWEF]yyyymmdd hh:mm:ss.uuuuuu threadid file:line] msg
+I20240412 08:00:00.100000 12345 deployment_tasks.cc:205] updating schemas.
+E20240412 08:00:00.200000 12345 config_data.cc:66] Error parsing YAML: yaml-cpp: error at line 3, column 1: end of map not found
+";
+
+    const AFTER: &str = "I20240412 08:30:00.100000 12345 deployment_tasks.cc:205] updating schemas.
+W20240412 08:30:00.110000 12345 config_data.cc:48] nonexistent config file '/Users/me/Library/Rime/sbsrf.custom.yaml'.
+I20240412 08:30:00.120000 12345 config_data.cc:43] loading config file '/Users/me/Library/Rime/build/sbsrf.schema.yaml'.
+E20240412 08:30:00.130000 12345 config_data.cc:66] Error parsing YAML: yaml-cpp: error at line 12, column 3: illegal map value
+I20240412 08:30:00.140000 12345 config_data.cc:43] loading config file '/Users/me/Library/Rime/default.yaml'.
+E20240412 08:30:00.150000 12345 config_compiler.cc:578] missing dependency: '/Users/me/Library/Rime/default.yaml' key: 'menu'
+F20240412 08:30:00.160000 12345 deployer.cc:120] Check failed: work_thread_.joinable()
+";
+
+    #[test]
+    fn parse_errors_reports_errors_and_fatals() {
+        assert_eq!(
+            parse_errors(AFTER),
+            [
+                "/Users/me/Library/Rime/build/sbsrf.schema.yaml:12: Error parsing YAML: yaml-cpp: error at line 12, column 3: illegal map value",
+                "missing dependency: '/Users/me/Library/Rime/default.yaml' key: 'menu'",
+                "/Users/me/Library/Rime/default.yaml: Check failed: work_thread_.joinable()",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_after_offset() {
+        const PREFIX: &str = "rime.sbsrf-update-test";
+        let path = env::temp_dir().join(format!("{PREFIX}.INFO.{}", std::process::id()));
+        fs::write(&path, BEFORE).unwrap();
+        let watch = DeployWatch {
+            prefix: Some(PREFIX),
+            offsets: HashMap::from([(path.clone(), BEFORE.len() as u64)]),
+        };
+        assert!(watch.errors().is_empty());
+
+        fs::write(&path, format!("{BEFORE}{AFTER}")).unwrap();
+        let errors = watch.errors();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(errors, parse_errors(AFTER));
+        assert_eq!(parse_errors(BEFORE).len(), 1);
+    }
+
+    #[test]
+    fn parse_errors_ignores_other_lines() {
+        assert!(parse_errors(AFTER.lines().next().unwrap()).is_empty());
+        assert!(parse_errors("E20240412 malformed line\nnot a log line\n").is_empty());
+    }
+}
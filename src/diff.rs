@@ -0,0 +1,372 @@
+use console::style;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use crate::{
+    backup::{BackupStore, Snapshot},
+    utils::list_files,
+};
+
+/// 显示差异时变化前后保留的行数
+const CONTEXT: usize = 3;
+/// 超过该大小的文件不显示文本差异
+const MAX_TEXT_SIZE: u64 = 1024 * 1024;
+/// 比较文本时使用的表格的最大单元格数，避免大文件占用过多内存
+const MAX_CELLS: usize = 4_000_000;
+/// 显示文本差异的文件类型
+const TEXT_EXTENSIONS: [&str; 6] = ["yaml", "yml", "txt", "lua", "json", "conf"];
+
+/// 参与比较的一组文件，可以是备份或目录
+pub enum Tree {
+    Snapshot(Snapshot),
+    /// 目录中被备份排除规则匹配的文件不参与比较
+    Dir(PathBuf),
+}
+
+impl Tree {
+    /// 所有文件的路径及内容的 SHA-256
+    fn entries(&self, store: &BackupStore) -> io::Result<BTreeMap<String, String>> {
+        match self {
+            Self::Snapshot(snapshot) => Ok(snapshot
+                .files
+                .iter()
+                .map(|x| (x.path.clone(), x.hash.clone()))
+                .collect()),
+            Self::Dir(dir) => {
+                let mut entries = BTreeMap::new();
+                for path in list_files(dir) {
+                    if store.is_excluded(&path) {
+                        continue;
+                    }
+
+                    let mut hasher = Sha256::new();
+                    io::copy(&mut File::open(dir.join(&path))?, &mut hasher)?;
+                    entries.insert(path, format!("{:x}", hasher.finalize()));
+                }
+                Ok(entries)
+            }
+        }
+    }
+
+    /// 读取文本文件的内容，文件过大或不是 UTF-8 编码时返回 None
+    fn read_text(&self, store: &BackupStore, path: &str, hash: &str) -> Option<String> {
+        let mut reader: Box<dyn Read> = match self {
+            Self::Snapshot(_) => store.open_object(hash).ok()?,
+            Self::Dir(dir) => Box::new(File::open(dir.join(path)).ok()?),
+        };
+
+        let mut content = vec![];
+        reader
+            .by_ref()
+            .take(MAX_TEXT_SIZE + 1)
+            .read_to_end(&mut content)
+            .ok()?;
+        if content.len() as u64 > MAX_TEXT_SIZE {
+            return None;
+        }
+        String::from_utf8(content).ok()
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Snapshot(snapshot) => snapshot.id.clone(),
+            Self::Dir(_) => "live".to_string(),
+        }
+    }
+}
+
+/// 显示两组文件之间新增、删除及修改的文件，文本文件同时显示内容的差异
+pub fn print_diff(store: &BackupStore, old: &Tree, new: &Tree) -> io::Result<()> {
+    let old_entries = old.entries(store)?;
+    let new_entries = new.entries(store)?;
+    let (old_name, new_name) = (old.name(), new.name());
+
+    let added: Vec<&String> = new_entries
+        .keys()
+        .filter(|x| !old_entries.contains_key(*x))
+        .collect();
+    let removed: Vec<&String> = old_entries
+        .keys()
+        .filter(|x| !new_entries.contains_key(*x))
+        .collect();
+    let modified: Vec<(&String, &String, &String)> = old_entries
+        .iter()
+        .filter_map(|(path, hash)| match new_entries.get(path) {
+            Some(new_hash) if new_hash != hash => Some((path, hash, new_hash)),
+            _ => None,
+        })
+        .collect();
+
+    println!(
+        "{} -> {}：新增 {} 个文件，删除 {} 个文件，修改 {} 个文件",
+        style(&old_name).cyan(),
+        style(&new_name).cyan(),
+        added.len(),
+        removed.len(),
+        modified.len()
+    );
+    for path in &added {
+        println!("  {} {path}", style("+").green());
+    }
+    for path in &removed {
+        println!("  {} {path}", style("-").red());
+    }
+    for (path, _, _) in &modified {
+        println!("  {} {path}", style("~").yellow());
+    }
+
+    for (path, old_hash, new_hash) in modified {
+        let extension = path.rsplit('.').next().unwrap_or_default();
+        if !TEXT_EXTENSIONS.contains(&extension) {
+            continue;
+        }
+
+        println!();
+        let old_text = old.read_text(store, path, old_hash);
+        let new_text = new.read_text(store, path, new_hash);
+        let hunks = match (old_text, new_text) {
+            (Some(old_text), Some(new_text)) => unified_diff(&old_text, &new_text),
+            _ => None,
+        };
+        let Some(hunks) = hunks else {
+            println!("{path}：文件过大或不是文本文件，不显示差异");
+            continue;
+        };
+
+        println!("{}", style(format!("--- {old_name}/{path}")).bold());
+        println!("{}", style(format!("+++ {new_name}/{path}")).bold());
+        for line in hunks {
+            match line.chars().next() {
+                Some('@') => println!("{}", style(line).cyan()),
+                Some('+') => println!("{}", style(line).green()),
+                Some('-') => println!("{}", style(line).red()),
+                _ => println!("{line}"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 统一格式的文本差异，文件过大时返回 None
+pub fn unified_diff(old: &str, new: &str) -> Option<Vec<String>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&old, &new)?;
+
+    // 每个操作之前已经过的旧文件及新文件的行数
+    let mut positions = vec![];
+    let (mut old_line, mut new_line) = (0, 0);
+    for (tag, _) in &ops {
+        positions.push((old_line, new_line));
+        match tag {
+            ' ' => {
+                old_line += 1;
+                new_line += 1;
+            }
+            '-' => old_line += 1,
+            _ => new_line += 1,
+        }
+    }
+
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, (tag, _))| *tag != ' ')
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut lines = vec![];
+    let mut i = 0;
+    while i < changes.len() {
+        // 相邻的变化之间的相同行不超过两倍的上下文时合并为一段
+        let mut last = i;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] <= 2 * CONTEXT + 1 {
+            last += 1;
+        }
+
+        let start = changes[i].saturating_sub(CONTEXT);
+        let end = (changes[last] + CONTEXT + 1).min(ops.len());
+        let hunk = &ops[start..end];
+        let old_count = hunk.iter().filter(|(tag, _)| *tag != '+').count();
+        let new_count = hunk.iter().filter(|(tag, _)| *tag != '-').count();
+        let (old_start, new_start) = positions[start];
+        lines.push(format!(
+            "@@ -{},{old_count} +{},{new_count} @@",
+            if old_count == 0 {
+                old_start
+            } else {
+                old_start + 1
+            },
+            if new_count == 0 {
+                new_start
+            } else {
+                new_start + 1
+            },
+        ));
+        lines.extend(hunk.iter().map(|(tag, line)| format!("{tag}{line}")));
+        i = last + 1;
+    }
+
+    Some(lines)
+}
+
+/// 基于最长公共子序列逐行比较，返回的每一行以 ' '、'-' 或 '+' 标记
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<(char, &'a str)>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    if (a.len() + 1) * (b.len() + 1) > MAX_CELLS {
+        return None;
+    }
+
+    // lcs[i * width + j] 为 a[i..] 与 b[j..] 的最长公共子序列的长度
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops: Vec<(char, &str)> = old[..prefix].iter().map(|x| (' ', *x)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            ops.push((' ', a[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            ops.push(('-', a[i]));
+            i += 1;
+        } else {
+            ops.push(('+', b[j]));
+            j += 1;
+        }
+    }
+    ops.extend(a[i..].iter().map(|x| ('-', *x)));
+    ops.extend(b[j..].iter().map(|x| ('+', *x)));
+    ops.extend(old[old.len() - suffix..].iter().map(|x| (' ', *x)));
+    Some(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 内容为 1 到 `count` 的行，跳过 `skip` 中的行
+    fn numbers(count: usize, skip: &[usize]) -> String {
+        (1..=count)
+            .filter(|x| !skip.contains(x))
+            .map(|x| format!("{x}\n"))
+            .collect()
+    }
+
+    #[test]
+    fn diff_lines_marks_changes() {
+        assert_eq!(
+            diff_lines(&["a", "b", "c"], &["a", "x", "c"]).unwrap(),
+            [(' ', "a"), ('-', "b"), ('+', "x"), (' ', "c")]
+        );
+        assert_eq!(
+            diff_lines(&["a", "b"], &["a", "b", "c"]).unwrap(),
+            [(' ', "a"), (' ', "b"), ('+', "c")]
+        );
+        assert!(diff_lines(&[], &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn same_content_has_no_hunks() {
+        assert!(unified_diff("a\nb\n", "a\nb\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn empty_side() {
+        assert_eq!(
+            unified_diff("", "a\nb\n").unwrap(),
+            ["@@ -0,0 +1,2 @@", "+a", "+b"]
+        );
+        assert_eq!(
+            unified_diff("a\nb\n", "").unwrap(),
+            ["@@ -1,2 +0,0 @@", "-a", "-b"]
+        );
+    }
+
+    #[test]
+    fn insert_only_with_trimmed_context() {
+        let new = numbers(10, &[]).replace("5\n", "5\nx\n");
+        assert_eq!(
+            unified_diff(&numbers(10, &[]), &new).unwrap(),
+            ["@@ -3,6 +3,7 @@", " 3", " 4", " 5", "+x", " 6", " 7", " 8"]
+        );
+    }
+
+    #[test]
+    fn delete_only_with_trimmed_context() {
+        assert_eq!(
+            unified_diff(&numbers(10, &[]), &numbers(10, &[5])).unwrap(),
+            ["@@ -2,7 +2,6 @@", " 2", " 3", " 4", "-5", " 6", " 7", " 8"]
+        );
+    }
+
+    #[test]
+    fn adjacent_hunks_are_merged() {
+        assert_eq!(
+            unified_diff(&numbers(20, &[]), &numbers(20, &[3, 9])).unwrap(),
+            [
+                "@@ -1,12 +1,10 @@",
+                " 1",
+                " 2",
+                "-3",
+                " 4",
+                " 5",
+                " 6",
+                " 7",
+                " 8",
+                "-9",
+                " 10",
+                " 11",
+                " 12",
+            ]
+        );
+    }
+
+    #[test]
+    fn distant_hunks_are_separate() {
+        assert_eq!(
+            unified_diff(&numbers(20, &[]), &numbers(20, &[3, 11])).unwrap(),
+            [
+                "@@ -1,6 +1,5 @@",
+                " 1",
+                " 2",
+                "-3",
+                " 4",
+                " 5",
+                " 6",
+                "@@ -8,7 +7,6 @@",
+                " 8",
+                " 9",
+                " 10",
+                "-11",
+                " 12",
+                " 13",
+                " 14",
+            ]
+        );
+    }
+}
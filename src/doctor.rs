@@ -0,0 +1,356 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::Path,
+    process::Command,
+    time::{Duration, SystemTime},
+};
+
+use console::style;
+use indicatif::HumanBytes;
+
+use crate::{
+    custom::device_patches,
+    im::IMUpdateConfig,
+    process::find_process,
+    release::{RateLimit, ReleaseSource, RELEASE_CACHE_DIR},
+    settings::Settings,
+    utils::{cache_dir, format_time, http_client, work_dir},
+};
+
+/// 可用空间低于该值时给出提示，完整的声笔发布及备份需要数百 MB
+const MIN_FREE_SPACE: u64 = 500 * 1024 * 1024;
+/// GitHub API 剩余额度低于该值时给出提示
+const MIN_RATE_LIMIT: u64 = 10;
+/// 连接仓输入法的超时时间
+const HAMSTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 诊断结果，逐项显示检查的内容，最后汇总问题及建议
+#[derive(Debug, Default)]
+pub struct Report {
+    warnings: usize,
+    errors: usize,
+    suggestions: Vec<String>,
+}
+
+impl Report {
+    pub fn section(&self, title: &str) {
+        println!("{}", style(title).bold());
+    }
+
+    pub fn ok(&mut self, message: impl Display) {
+        println!("  {} {message}", style("✓").green());
+    }
+
+    pub fn warn(&mut self, message: impl Display) {
+        self.warnings += 1;
+        println!("  {} {message}", style("!").yellow());
+    }
+
+    pub fn error(&mut self, message: impl Display) {
+        self.errors += 1;
+        println!("  {} {message}", style("x").red());
+    }
+
+    /// 添加解决建议，相同的建议只保留一次
+    pub fn suggest(&mut self, suggestion: impl Display) {
+        let suggestion = suggestion.to_string();
+        if !self.suggestions.contains(&suggestion) {
+            self.suggestions.push(suggestion);
+        }
+    }
+
+    /// 显示问题数量及建议，有错误时返回 false
+    pub fn print_summary(&self) -> bool {
+        println!();
+        if self.errors == 0 && self.warnings == 0 {
+            println!("{}", style("没有发现问题").green());
+            return true;
+        }
+
+        println!(
+            "{}",
+            style(format!(
+                "发现 {} 个错误，{} 个警告",
+                self.errors, self.warnings
+            ))
+            .yellow()
+        );
+        if !self.suggestions.is_empty() {
+            println!("建议：");
+            for (i, suggestion) in self.suggestions.iter().enumerate() {
+                println!("  {}. {suggestion}", i + 1);
+            }
+        }
+        self.errors == 0
+    }
+}
+
+/// 目录所在磁盘的可用空间
+#[cfg(unix)]
+fn free_space(dir: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+    // Filesystem 1024-blocks Used Available Capacity Mounted on
+    let output = String::from_utf8_lossy(&output.stdout);
+    let available = output.lines().nth(1)?.split_whitespace().nth(3)?;
+    available.parse::<u64>().ok().map(|x| x * 1024)
+}
+
+#[cfg(windows)]
+fn free_space(dir: &Path) -> Option<u64> {
+    let path = dir.to_string_lossy().replace('\'', "''");
+    let output = Command::new("powershell")
+        .args([
+            "-NoProfile",
+            "-Command",
+            &format!("(Get-Item -LiteralPath '{path}').PSDrive.Free"),
+        ])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// 检查目录是否存在、可写及所在磁盘的可用空间，目录可用时返回 true
+fn check_dir(report: &mut Report, label: &str, dir: &Path) -> bool {
+    if !dir.is_dir() {
+        report.error(format!("{label} {} 不存在", dir.display()));
+        return false;
+    }
+    if let Err(error) = tempfile::tempfile_in(dir) {
+        report.error(format!("{label} {} 不可写：{error}", dir.display()));
+        report.suggest(format!("检查 {} 的权限", dir.display()));
+        return false;
+    }
+
+    match free_space(dir) {
+        Some(free) if free < MIN_FREE_SPACE => {
+            report.warn(format!(
+                "{label} {} 所在磁盘只剩 {}",
+                dir.display(),
+                HumanBytes(free)
+            ));
+            report.suggest("清理磁盘空间，或通过 backup delete 删除不需要的备份");
+        }
+        Some(free) => report.ok(format!(
+            "{label} {}，可用空间 {}",
+            dir.display(),
+            HumanBytes(free)
+        )),
+        None => report.ok(format!("{label} {}", dir.display())),
+    }
+    true
+}
+
+/// 全局设置能否解析，以及工作目录是否可用
+pub fn check_settings(report: &mut Report) {
+    match Settings::load() {
+        Ok(_) if Settings::path().exists() => {
+            report.ok(format!("设置文件 {}", Settings::path().display()))
+        }
+        Ok(_) => report.ok("没有设置文件，使用默认设置"),
+        Err(error) => {
+            report.error(error);
+            report.suggest(format!(
+                "修正设置文件 {} 中的错误，或删除该文件使用默认设置",
+                Settings::path().display()
+            ));
+        }
+    }
+    check_dir(report, "工作目录", &work_dir());
+}
+
+/// 检查名为 `process` 的输入法进程及其设备配置 `device`，只读取已有的配置，不会像 update 等命令那样自动创建
+pub fn check_input_method(report: &mut Report, label: &str, process: &str, device: &str) {
+    let Some(process) = find_process(process) else {
+        report.ok(format!("{label}：未运行"));
+        return;
+    };
+
+    match IMUpdateConfig::new(device) {
+        Ok(Some(config)) => match &config.exe {
+            Some(exe) if exe.exists() => report.ok(format!("{label}：{}", exe.display())),
+            Some(exe) => {
+                report.error(format!("{label} 的可执行文件 {} 不存在", exe.display()));
+                report.suggest(format!(
+                    "修改 {} 中的 exe，或重新安装{label}",
+                    config.update_dir.join("config.toml").display()
+                ));
+            }
+            None => report.warn(format!("{label}：没有找到可执行文件")),
+        },
+        Ok(None) => {
+            let exe = process.exe.map_or("无法读取程序路径".to_string(), |x| {
+                x.display().to_string()
+            });
+            report.ok(format!("{label}：正在运行（{exe}），还没有设备配置"));
+            report.suggest(format!("执行 update 时会自动为{label}创建设备配置"));
+        }
+        Err(error) => {
+            report.error(format!("{label}：读取设备配置失败，{error}"));
+            report.suggest(format!(
+                "修正{label}的设备配置，或通过 device remove 删除后重新检测"
+            ));
+        }
+    }
+}
+
+/// 检查设备配置中的程序、用户目录及补丁集，仓输入法只在指定了地址时检查能否连接
+pub async fn check_device(report: &mut Report, config: &IMUpdateConfig, host: Option<&String>) {
+    report.ok(format!(
+        "{}，版本 {}，{} 通道",
+        config.name, config.version, config.channel
+    ));
+
+    if config.name == "Hamster" {
+        check_hamster(report, host).await;
+    } else {
+        match &config.exe {
+            Some(exe) if !exe.exists() => {
+                report.error(format!("可执行文件 {} 不存在", exe.display()));
+                report.suggest(format!(
+                    "修改 {} 中的 exe，或重新安装输入法",
+                    config.update_dir.join("config.toml").display()
+                ));
+            }
+            Some(_) => {}
+            None => {
+                report.warn("没有设置可执行文件，更新后无法自动重新部署");
+            }
+        }
+
+        if !check_dir(report, "用户目录", &config.user_dir) {
+            report.suggest("确认输入法已至少运行过一次，或修改设备配置中的 user_dir");
+        }
+    }
+
+    if let Err(error) = device_patches(config) {
+        report.error(format!("补丁集：{error}"));
+        report.suggest("通过 custom disable 停用不存在的补丁集");
+    }
+}
+
+async fn check_hamster(report: &mut Report, host: Option<&String>) {
+    let Some(host) = host else {
+        report.warn("没有指定设备地址，跳过连接检查");
+        report.suggest("通过 doctor -H <地址> 检查能否连接仓输入法");
+        return;
+    };
+
+    let result = http_client()
+        .get(format!("http://{host}/"))
+        .timeout(HAMSTER_TIMEOUT)
+        .send()
+        .await;
+    match result {
+        Ok(_) => report.ok(format!("可以连接 {host}")),
+        Err(error) => {
+            report.error(format!("无法连接 {host}：{error}"));
+            report.suggest(
+                "确认手机与电脑在同一网络，且仓输入法已打开 'Wi-Fi 上传方案'，期间不要锁屏",
+            );
+        }
+    }
+}
+
+/// 检查发布来源能否访问，使用 GitHub 时同时检查 API 的剩余额度
+pub async fn check_sources(report: &mut Report, settings: &Settings, sources: &[ReleaseSource]) {
+    let mut github = false;
+    for source in sources {
+        github |= source.is_github();
+        match source.check(settings).await {
+            Ok(_) => report.ok(format!("可以访问 {source}")),
+            Err(error) if error.is::<reqwest::Error>() => {
+                report.error(format!("无法访问 {source}：{error}"));
+                report.suggest(
+                    "检查网络连接，需要代理时通过 --proxy 或设置文件中的 network.proxy 设置",
+                );
+            }
+            Err(error) => {
+                report.error(format!("{source}：{error}"));
+                report.suggest("检查设置文件或设备配置中的 [source]");
+            }
+        }
+    }
+    if !github {
+        return;
+    }
+
+    match RateLimit::fetch(settings).await {
+        Ok(limit) if limit.remaining < MIN_RATE_LIMIT => {
+            report.warn(format!(
+                "GitHub API 剩余额度 {}/{}，将在 {} 分钟后重置",
+                limit.remaining,
+                limit.limit,
+                limit.minutes_to_reset()
+            ));
+            if !limit.authenticated {
+                report.suggest(
+                    "通过 GITHUB_TOKEN 环境变量或设置文件中的 github.token 使用令牌访问以提高限额",
+                );
+            }
+        }
+        Ok(limit) => report.ok(format!(
+            "GitHub API 剩余额度 {}/{}{}",
+            limit.remaining,
+            limit.limit,
+            if limit.authenticated {
+                "（使用令牌）"
+            } else {
+                ""
+            }
+        )),
+        Err(error) => report.warn(format!("查询 GitHub API 额度失败：{error}")),
+    }
+}
+
+/// 缓存的发布文件及发布信息，离线模式依赖这些缓存
+pub fn check_cache(report: &mut Report) {
+    let dir = cache_dir();
+    let version = fs::read_to_string(dir.join("version.info")).ok();
+    let mut count = 0;
+    let mut size = 0;
+    for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_file() && entry.file_name() != "version.info" {
+            count += 1;
+            size += metadata.len();
+        }
+    }
+
+    match version {
+        Some(version) => report.ok(format!(
+            "缓存了版本 {version} 的 {count} 个发布文件，共 {}",
+            HumanBytes(size)
+        )),
+        None if count == 0 => report.ok("没有缓存的发布文件"),
+        None => {
+            report.warn(format!("缓存目录中有 {count} 个文件，但没有版本信息"));
+            report.suggest("执行 clean 清理缓存目录");
+        }
+    }
+
+    let mut releases = 0;
+    let mut latest: Option<SystemTime> = None;
+    for entry in fs::read_dir(dir.join(RELEASE_CACHE_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        releases += 1;
+        if let Ok(modified) = entry.metadata().and_then(|x| x.modified()) {
+            latest = latest.max(Some(modified));
+        }
+    }
+    match latest {
+        Some(latest) => report.ok(format!(
+            "缓存了 {releases} 个发布信息，最后更新于 {}",
+            format_time(latest)
+        )),
+        None => {
+            report.warn("没有缓存的发布信息，无法使用 --offline");
+            report.suggest("联网执行一次 check 或 update 以缓存发布信息");
+        }
+    }
+}
@@ -1,17 +1,18 @@
-use std::{
-    fs,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
 
 use dialoguer::{theme::ColorfulTheme, Confirm};
 use indicatif::{MultiProgress, ProgressBar};
 use tempfile::tempdir;
 
 use crate::{
-    im::{check_file_item, IMUpdateConfig, InputMethod},
+    backup::{finish_backup, prepare_backup, BackupReason, BackupStore, Snapshot},
+    custom::apply_patches,
+    im::{select_assets, AssetRules, IMUpdateConfig, InputMethod},
+    release::{Channel, ReleaseSource},
+    retention::Retention,
     utils::{
-        download_and_install, download_file, get_bar_style, get_spinner_style, unzip,
-        upload_to_ios, work_dir,
+        download_and_install, download_file, get_bar_style, get_spinner_style, upload_to_ios,
+        work_dir,
     },
 };
 
@@ -26,6 +27,47 @@ impl Hamster {
         Self { config, host }
     }
 
+    /// 将设备上的 Rime 目录下载为压缩包，其中的文件都位于 Rime/ 下
+    pub async fn download_rime(
+        &self,
+        zip_path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let pb = ProgressBar::new(100);
+        pb.set_style(get_bar_style());
+        pb.set_prefix("下载 Rime 目录");
+
+        let url = format!("http://{}/api/raw/Rime", &self.host);
+        let result = download_file(url, &zip_path.to_path_buf(), |len, total| {
+            pb.set_length(total);
+            pb.inc(len as u64);
+        })
+        .await;
+        pb.finish();
+        result
+    }
+
+    /// 将启用的自定义补丁上传到设备的 Rime 目录
+    pub async fn upload_patches(&self) {
+        let temp_dir = tempdir().unwrap();
+        let files = match apply_patches(&self.config, temp_dir.path()) {
+            Ok(files) => files,
+            Err(error) => {
+                println!("应用自定义补丁失败：{error}");
+                return;
+            }
+        };
+
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(get_spinner_style());
+        pb.set_prefix("上传");
+        let result = upload_to_ios(temp_dir.path(), &self.host, &pb).await;
+        pb.finish_and_clear();
+        match result {
+            Ok(_) => println!("已应用自定义补丁：{}", files.join("、")),
+            Err(error) => println!("上传自定义补丁失败：{error}"),
+        }
+    }
+
     pub fn default_config(name: &str) -> IMUpdateConfig {
         let update_dir = work_dir().join(name);
 
@@ -36,7 +78,14 @@ impl Hamster {
             update_dir,
             max_backups: 1,
             sentence: false,
+            page_size: None,
             version: "20051203".to_string(),
+            channel: Channel::default(),
+            assets: AssetRules::default(),
+            source: ReleaseSource::default(),
+            retention: Retention::default(),
+            sync: false,
+            custom: vec![],
         }
     }
 }
@@ -46,15 +95,16 @@ async fn download_and_upload_to_ios(
     download_url: String,
     device_host: String,
     m: MultiProgress,
-) {
+) -> Result<(), String> {
     let target_dir = tempdir().unwrap().into_path();
-    download_and_install(target_dir.clone(), name, download_url, m.clone()).await;
+    download_and_install(target_dir.clone(), name.clone(), download_url, m.clone()).await;
 
     let pb = m.add(ProgressBar::new_spinner());
     pb.set_style(get_spinner_style());
     pb.set_prefix("上传");
-    upload_to_ios(&target_dir, &device_host, &pb).await.unwrap();
-    pb.finish_with_message("完成");
+    let result = upload_to_ios(&target_dir, &device_host, &pb).await;
+    pb.finish_and_clear();
+    result.map_err(|error| format!("上传 {name} 失败：{error}"))
 }
 
 impl InputMethod for Hamster {
@@ -62,33 +112,32 @@ impl InputMethod for Hamster {
         todo!()
     }
 
-    async fn backup(&self) {
-        let pb = ProgressBar::new(100);
-        pb.set_style(get_bar_style());
-        pb.set_prefix("备份");
+    async fn backup(&self, reason: BackupReason, label: Option<String>) -> Option<Snapshot> {
+        let store = prepare_backup(&self.config, reason)?;
 
-        let url = format!("http://{}/api/raw/Rime", &self.host);
-        let target_path = self
-            .config
-            .update_dir
-            .join("backups")
-            .join(&self.config.version);
-        if !target_path.exists() {
-            fs::create_dir_all(&target_path).unwrap();
+        let temp_dir = tempdir().unwrap();
+        let zip_path = temp_dir.path().join("Rime.zip");
+        if let Err(err) = self.download_rime(&zip_path).await {
+            eprintln!("备份失败: {}", err);
+            return None;
         }
 
-        if let Err(err) = download_file(url, &target_path.join("Rime.zip"), |len, total| {
-            pb.set_length(total);
-            pb.inc(len as u64);
-        })
-        .await
-        {
-            eprintln!("备份失败: {}", err);
+        let snapshot = store.new_snapshot(&self.config.version, reason, label);
+        match store.create_from_zip(snapshot, &zip_path, "Rime/") {
+            Ok(snapshot) => {
+                println!("已备份为 {}", snapshot.id);
+                finish_backup(&store, &self.config, &snapshot);
+                Some(snapshot)
+            }
+            Err(err) => {
+                eprintln!("备份失败: {}", err);
+                None
+            }
         }
-        pb.finish();
     }
 
-    async fn restore(&self, version: &Path) {
+    /// 设备上的其它文件不会被删除，因此 `partial` 不影响还原的方式
+    async fn restore(&self, snapshot: &Snapshot, _partial: bool) {
         let confirmation = Confirm::with_theme(&ColorfulTheme::default())
             .with_prompt("ios 设备是否已经打开 'Wi-Fi 上传方案' 且与当前终端连接到了同一网络？")
             .default(false)
@@ -100,25 +149,37 @@ impl InputMethod for Hamster {
             return;
         }
 
-        // 解压
-        let file_path = version.join("Rime.zip");
-        let output_dir = work_dir().join("_cache");
+        // 从备份中取出文件
+        let temp_dir = tempdir().unwrap();
+        let from = temp_dir.path().join("Rime");
         let pb = ProgressBar::new_spinner();
         pb.set_style(get_spinner_style());
-        pb.set_prefix("解压");
-        unzip(&file_path, &output_dir, &pb).await;
+        pb.set_prefix("读取备份");
+        if let Err(err) = BackupStore::open(&self.config).and_then(|store| {
+            store.restore_to_dir(snapshot, &from, |path| {
+                pb.set_message(path.to_string());
+                pb.inc(1);
+            })
+        }) {
+            eprintln!("读取备份失败: {}", err);
+            return;
+        }
+        pb.finish_with_message("完成");
 
         let pb = ProgressBar::new_spinner();
         pb.set_style(get_spinner_style());
         pb.set_prefix("上传");
-        let from = output_dir.join("Rime");
-        upload_to_ios(&from, &self.host, &pb).await.unwrap();
-        fs::remove_dir_all(from).unwrap();
+        let result = upload_to_ios(&from, &self.host, &pb).await;
+        pb.finish_and_clear();
+        if let Err(err) = result {
+            eprintln!("还原失败: {}", err);
+            return;
+        }
 
         println!("还原完成，需要在手机上重新部署");
     }
 
-    async fn update(&self, release: crate::release::Release) {
+    async fn update(&self, release: crate::release::Release) -> Result<Option<Snapshot>, String> {
         let confirmation = Confirm::with_theme(&ColorfulTheme::default())
             .with_prompt("ios 设备是否已经打开 'Wi-Fi 上传方案' 且与当前终端连接到了同一网络？")
             .default(false)
@@ -126,22 +187,22 @@ impl InputMethod for Hamster {
             .unwrap();
 
         if !confirmation {
-            println!("ios 设备升级时需要与当前终端处于同一网络，且已打开仓输入法的 Wi-Fi 上传方案。在更新期间不要关闭 ios 设备屏幕，否则会导致更新失败");
-            return;
+            return Err("ios 设备升级时需要与当前终端处于同一网络，且已打开仓输入法的 Wi-Fi 上传方案。在更新期间不要关闭 ios 设备屏幕，否则会导致更新失败".to_string());
         }
 
         println!("开始为本地的鼠须管更新声笔输入法...");
-        self.backup().await;
+        let snapshot = self.backup(BackupReason::Update, None).await;
 
         let m = MultiProgress::new();
         let mut tasks = vec![];
         let host = self.host.clone();
 
-        for asset in release.assets {
-            if !check_file_item(&asset.name, "hamster", self.config.sentence) {
+        for selection in select_assets(&release, &self.config) {
+            if !selection.selected {
                 continue;
             }
 
+            let asset = selection.asset;
             let name = asset.name;
             let download_url = asset.download_url;
             let task = tokio::spawn(download_and_upload_to_ios(
@@ -153,16 +214,39 @@ impl InputMethod for Hamster {
             tasks.push(task);
         }
 
+        let mut failed = false;
         for task in tasks {
-            if let Err(error) = task.await {
-                println!("更新失败：{error}");
+            match task.await {
+                Ok(Ok(())) => {}
+                Ok(Err(error)) => {
+                    failed = true;
+                    println!("{error}");
+                }
+                Err(error) => {
+                    failed = true;
+                    println!("更新失败：{error}");
+                }
             }
         }
+        // 上传失败时不记录新版本，下次仍会提示更新
+        if failed {
+            return Err("更新未完成，请确认手机与电脑在同一网络且没有锁屏后重试".to_string());
+        }
+
+        if !self.config.custom.is_empty() {
+            self.upload_patches().await;
+        }
 
         println!("更新完成，需要在手机上重新部署");
+        Ok(snapshot)
     }
 
     fn deploy(&self) {
         todo!()
     }
+
+    /// 仓输入法没有提供远程同步的接口
+    fn sync(&self) {
+        println!("需要在手机上的仓输入法中手动同步用户数据");
+    }
 }
@@ -1,21 +1,98 @@
-use serde::{Deserialize, Serialize};
-use std::{
-    fs,
-    path::{Path, PathBuf},
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{fs, path::PathBuf};
+
+use crate::{
+    backup::{BackupReason, Snapshot},
+    release::{Asset, Channel, Release, ReleaseSource},
+    retention::Retention,
+    utils::{data_dir, glob_match, glob_to_regex, work_dir},
 };
 
-use crate::{release::Release, utils::work_dir};
+/// 发布文件的筛选规则，在默认规则的基础上增加或排除文件
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct AssetRules {
+    /// 额外安装的文件，支持通配符
+    pub include: Vec<String>,
+    /// 不安装的文件，支持通配符，优先于其它规则
+    pub exclude: Vec<String>,
+}
 
-pub fn check_file_item(name: &str, im: &str, sentence: bool) -> bool {
-    if name.starts_with("sbsrf") {
-        return true;
+impl AssetRules {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
     }
 
-    if name.starts_with("octagram") {
-        return sentence;
+    /// 检查规则是否能匹配到发布中的文件，返回有问题的规则说明
+    pub fn validate(&self, release: &Release) -> Vec<String> {
+        let mut problems = vec![];
+        for (kind, patterns) in [("include", &self.include), ("exclude", &self.exclude)] {
+            for pattern in patterns {
+                if glob_to_regex(pattern).is_err() {
+                    problems.push(format!("{kind} 规则 {pattern} 无效"));
+                } else if !release.assets.iter().any(|x| glob_match(pattern, &x.name)) {
+                    problems.push(format!(
+                        "{kind} 规则 {pattern} 没有匹配到版本 {} 中的任何文件",
+                        release.version
+                    ));
+                }
+            }
+        }
+        problems
     }
+}
+
+/// 发布文件的筛选结果
+#[derive(Debug, Clone)]
+pub struct AssetSelection {
+    pub asset: Asset,
+    pub selected: bool,
+    /// 选中或未选中的原因
+    pub reason: String,
+}
+
+/// 根据设备的输入法、整句设置及筛选规则决定需要安装哪些发布文件
+pub fn select_assets(release: &Release, config: &IMUpdateConfig) -> Vec<AssetSelection> {
+    let im = config.name.to_lowercase();
+    release
+        .assets
+        .iter()
+        .map(|asset| {
+            let name = &asset.name;
+            let (selected, reason) = if let Some(pattern) =
+                config.assets.exclude.iter().find(|p| glob_match(p, name))
+            {
+                (false, format!("匹配 exclude 规则 {pattern}"))
+            } else if name.starts_with("sbsrf") {
+                (true, "声笔方案文件".to_string())
+            } else if name.starts_with("octagram") {
+                if config.sentence {
+                    (true, "已启用整句输入".to_string())
+                } else {
+                    (false, "未启用整句输入".to_string())
+                }
+            } else if name.starts_with(&im) {
+                (true, format!("{} 专用文件", config.name))
+            } else if let Some(pattern) = config.assets.include.iter().find(|p| glob_match(p, name))
+            {
+                (true, format!("匹配 include 规则 {pattern}"))
+            } else {
+                (false, "其它输入法的文件".to_string())
+            };
+
+            AssetSelection {
+                asset: asset.clone(),
+                selected,
+                reason,
+            }
+        })
+        .collect()
+}
 
-    name.starts_with(&im.to_lowercase())
+/// 旧版本的 max_backups 可以是负数，按 0 处理，即更新前不备份
+fn deserialize_max_backups<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let value = i64::deserialize(deserializer)?;
+    Ok(value.clamp(0, u32::MAX.into()) as u32)
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -28,12 +105,34 @@ pub struct IMUpdateConfig {
     pub user_dir: PathBuf,
     /// 更新目录
     pub update_dir: PathBuf,
-    /// 最大备份数量
-    pub max_backups: i32,
+    /// 最大备份数量，即保留最近的几个自动备份
+    #[serde(deserialize_with = "deserialize_max_backups")]
+    pub max_backups: u32,
     /// 是否使用整句世入方案
     pub sentence: bool,
+    /// 候选数量，未设置时使用方案的默认值
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<u8>,
     /// 当前版本
     pub version: String,
+    /// 发布通道：stable、prerelease 或标签通配符
+    #[serde(default, skip_serializing_if = "Channel::is_stable")]
+    pub channel: Channel,
+    /// 发布文件的筛选规则
+    #[serde(default, skip_serializing_if = "AssetRules::is_empty")]
+    pub assets: AssetRules,
+    /// 发布信息的来源，未设置的项使用全局设置
+    #[serde(default, skip_serializing_if = "ReleaseSource::is_default")]
+    pub source: ReleaseSource,
+    /// 备份的保留策略
+    #[serde(default, skip_serializing_if = "Retention::is_empty")]
+    pub retention: Retention,
+    /// 更新后同步用户数据
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sync: bool,
+    /// 启用的自定义补丁集，更新后写入用户目录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom: Vec<String>,
 }
 
 impl IMUpdateConfig {
@@ -42,7 +141,11 @@ impl IMUpdateConfig {
         if config_file.exists() {
             // 配置文件存在，直接读取
             let toml = std::fs::read_to_string(config_file)?;
-            let config: Self = toml::from_str(&toml)?;
+            let mut config: Self = toml::from_str(&toml)?;
+            // 工作目录可能被迁移或重新指定过，以实际读取到的位置为准
+            if let Some(dir_name) = config.update_dir.file_name() {
+                config.update_dir = work_dir().join(dir_name);
+            }
             return Ok(Some(config));
         }
 
@@ -58,6 +161,17 @@ impl IMUpdateConfig {
         std::os::unix::fs::symlink(self.update_dir.clone(), new_dir).unwrap();
     }
 
+    /// 设备名称，即设备配置所在目录的名称
+    pub fn device(&self) -> String {
+        let dir_name = self.update_dir.file_name().unwrap_or_default();
+        dir_name.to_string_lossy().to_string()
+    }
+
+    /// 备份目录
+    pub fn backup_dir(&self) -> PathBuf {
+        data_dir().join(self.device()).join("backups")
+    }
+
     pub fn save(&mut self, version: &str) {
         self.version = version.to_string();
         self.write_config();
@@ -73,6 +187,20 @@ impl IMUpdateConfig {
     }
 }
 
+/// 已添加的设备名称
+pub fn device_names() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(work_dir())
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('_'))
+        .collect();
+    names.sort();
+    names
+}
+
 pub trait InputMethod {
     /**
      * 安装
@@ -80,22 +208,27 @@ pub trait InputMethod {
     async fn install(&self, name: &str, download_url: &str);
 
     /**
-     * 备份
+     * 备份，返回创建的备份
      */
-    async fn backup(&self);
+    async fn backup(&self, reason: BackupReason, label: Option<String>) -> Option<Snapshot>;
 
     /**
-     * 回滚
+     * 回滚，`partial` 为 true 时只还原备份中的文件，不删除其它文件
      */
-    async fn restore(&self, version: &Path);
+    async fn restore(&self, snapshot: &Snapshot, partial: bool);
 
     /**
-     * 更新
+     * 更新，返回更新前创建的备份，更新未完成时返回原因
      */
-    async fn update(&self, release: Release);
+    async fn update(&self, release: Release) -> Result<Option<Snapshot>, String>;
 
     /**
      * 部署
      */
     fn deploy(&self);
+
+    /**
+     * 同步用户数据
+     */
+    fn sync(&self);
 }
@@ -0,0 +1,182 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use console::style;
+
+use crate::utils::format_time;
+
+/// Rime 用户目录中的安装信息文件
+pub const INSTALLATION_FILE: &str = "installation.yaml";
+
+/// 等待输入法完成同步的最长时间
+const SYNC_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Rime 用户目录中的 installation.yaml，其中的 installation_id 及 sync_dir 决定同步用户数据的位置
+///
+/// 文件只有一层 `key: value`，按行读写以保留输入法写入的其它内容
+#[derive(Debug, Clone, Default)]
+pub struct Installation {
+    lines: Vec<String>,
+}
+
+impl Installation {
+    pub fn parse(content: &str) -> Self {
+        Self {
+            lines: content.lines().map(str::to_string).collect(),
+        }
+    }
+
+    /// 读取用户目录中的 installation.yaml，文件不存在时返回空的安装信息
+    pub fn read(user_dir: &Path) -> io::Result<Self> {
+        match fs::read_to_string(user_dir.join(INSTALLATION_FILE)) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn write(&self, user_dir: &Path) -> io::Result<()> {
+        fs::write(user_dir.join(INSTALLATION_FILE), self.to_string())
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.lines.iter().find_map(|line| {
+            let value = value_of(line, key)?.trim();
+            let value = match value.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+                Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+                None => value.trim_matches('\'').to_string(),
+            };
+            (!value.is_empty()).then_some(value)
+        })
+    }
+
+    /// 设置的值总是以双引号包围，已有的项在原位置修改，否则添加到末尾
+    pub fn set(&mut self, key: &str, value: &str) {
+        let line = format!(
+            "{key}: \"{}\"",
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+        let existing = self.lines.iter_mut().find(|x| value_of(x, key).is_some());
+        match existing {
+            Some(existing) => *existing = line,
+            None => self.lines.push(line),
+        }
+    }
+
+    pub fn installation_id(&self) -> Option<String> {
+        self.get("installation_id")
+    }
+
+    /// 同步目录，未设置时为用户目录下的 sync
+    pub fn sync_dir(&self, user_dir: &Path) -> PathBuf {
+        match self.get("sync_dir") {
+            Some(dir) => PathBuf::from(dir),
+            None => user_dir.join("sync"),
+        }
+    }
+}
+
+/// 顶层的 `key: value` 行中的值
+fn value_of<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let (k, value) = line.split_once(':')?;
+    (k.trim() == key).then_some(value)
+}
+
+impl std::fmt::Display for Installation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 目录中的文件数量及最后修改时间
+fn dir_summary(dir: &Path) -> (usize, Option<SystemTime>) {
+    let mut count = 0;
+    let mut latest = None;
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+
+        count += 1;
+        if let Ok(modified) = metadata.modified() {
+            latest = latest.max(Some(modified));
+        }
+    }
+    (count, latest)
+}
+
+/// 显示安装信息及同步目录中各设备的同步情况
+pub fn print_sync_status(installation: &Installation, user_dir: &Path) {
+    let id = installation.installation_id();
+    let sync_dir = installation.sync_dir(user_dir);
+    println!(
+        "installation_id：{}",
+        id.as_deref().unwrap_or("未设置，输入法部署时会自动生成")
+    );
+    match installation.get("sync_dir") {
+        Some(dir) => println!("同步目录：{dir}"),
+        None => println!("同步目录：用户目录下的 sync（默认）"),
+    }
+
+    let Ok(entries) = fs::read_dir(&sync_dir) else {
+        println!("  同步目录不存在或无法访问，尚未同步过用户数据");
+        return;
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| x.is_dir())
+        .collect();
+    dirs.sort();
+    if dirs.is_empty() {
+        println!("  同步目录中没有任何设备的数据");
+    }
+    for dir in dirs {
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        let (count, latest) = dir_summary(&dir);
+        let current = if id.as_deref() == Some(&name) {
+            style("（本设备）").green().to_string()
+        } else {
+            String::new()
+        };
+        println!(
+            "  {name}{current}：{count} 个文件，最后同步于 {}",
+            latest.map(format_time).unwrap_or("-".to_string())
+        );
+    }
+}
+
+/// 等待输入法将本设备的数据写入同步目录，超时返回 false
+pub async fn wait_for_sync(
+    installation: &Installation,
+    user_dir: &Path,
+    since: SystemTime,
+) -> bool {
+    let Some(id) = installation.installation_id() else {
+        return false;
+    };
+    let dir = installation.sync_dir(user_dir).join(id);
+
+    let start = SystemTime::now();
+    while start.elapsed().unwrap_or_default() < SYNC_TIMEOUT {
+        if let (_, Some(latest)) = dir_summary(&dir) {
+            if latest >= since {
+                return true;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    false
+}
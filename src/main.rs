@@ -1,24 +1,56 @@
+// 本地输入法的后端只在 macOS 及 Windows 下编译，其它平台上部分公共代码不会被用到
+#![cfg_attr(not(any(target_os = "macos", target_os = "windows")), allow(dead_code))]
+
+mod backup;
+mod custom;
+mod deployment;
+mod diff;
+mod doctor;
 #[cfg(target_os = "macos")]
 mod fcitx5;
 mod hamster;
 mod im;
+mod installation;
+mod options;
+mod plan;
+mod process;
 mod release;
+mod retention;
+mod settings;
 #[cfg(target_os = "macos")]
 mod squirrel;
+mod userdb;
 mod utils;
+mod validate;
 #[cfg(target_os = "windows")]
 mod weasel;
 
+use backup::{BackupReason, BackupStore, Snapshot};
 use clap::{Arg, ArgAction, Command};
 use console::style;
-use dialoguer::{theme::ColorfulTheme, Confirm, Select};
+use deployment::DeployWatch;
+use dialoguer::{theme::ColorfulTheme, Confirm, MultiSelect, Select};
+use diff::{print_diff, Tree};
+use doctor::Report;
 use hamster::Hamster;
-use im::{IMUpdateConfig, InputMethod};
-use release::Release;
+use im::{device_names, IMUpdateConfig, InputMethod};
+use indicatif::{HumanBytes, ProgressBar};
+use installation::{print_sync_status, wait_for_sync, Installation, INSTALLATION_FILE};
+use options::{PatchFile, PATCH_FILE};
+use plan::{print_restore_plan, print_update_plan};
+use release::{Channel, Release, ReleaseSource, RELEASE_CACHE_DIR};
+use settings::Settings;
+use userdb::UserDb;
+use std::collections::{BTreeMap, HashMap};
 use std::io::Write;
 use std::{env::consts::OS, fs::read_to_string};
 use std::fs::{self, create_dir_all};
-use utils::{open, work_dir};
+use std::path::{Path, PathBuf};
+use utils::{
+    cache_dir, data_dir, get_spinner_style, init_http_client, init_work_dir, open, set_offline,
+    upload_to_ios, work_dir,
+};
+use validate::{fetch_assets, print_problems, validate_release, validate_user_dir};
 
 #[cfg(target_os = "macos")]
 use {
@@ -27,7 +59,7 @@ use {
 };
 
 #[cfg(target_os = "windows")]
-use weasel::{get_weasel, Weasel};
+use weasel::{get_weasel, Weasel, WEASEL_SERVER};
 
 #[cfg(target_os = "macos")]
 async fn install_if_needed(release: &Release) {
@@ -153,18 +185,57 @@ async fn install_if_needed(release: &Release) {
     }
 }
 
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+async fn install_if_needed(_: &Release) {
+    if let Ok(Some(_)) = IMUpdateConfig::new(OS) {
+        return;
+    }
+
+    println!("当前系统暂不支持自动安装输入法程序，可以通过 \"sbsrf-update device add\" 添加远程设备后进行更新");
+}
+
+/// 检测正在运行的输入法程序，与安装时的检测方式相同
+#[cfg(target_os = "macos")]
+fn detect_input_methods(report: &mut Report) {
+    doctor::check_input_method(report, "鼠须管", "Squirrel", "Squirrel");
+    doctor::check_input_method(report, "小企鹅", "Fcitx5", "Fcitx5");
+}
+
+#[cfg(target_os = "windows")]
+fn detect_input_methods(report: &mut Report) {
+    doctor::check_input_method(report, "小狼毫", WEASEL_SERVER, OS);
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn detect_input_methods(report: &mut Report) {
+    report.warn("当前系统不支持检测本地的输入法程序，只能更新仓输入法等远程设备");
+}
+
 async fn update(
-    release: Release,
+    settings: &Settings,
     name: &str,
     host: Option<&String>,
+    offline: bool,
+    dry_run: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     if let Ok(Some(config)) = IMUpdateConfig::new(name) {
+        // 获取发布信息
+        let source = config.source.or(&settings.release);
+        let release = Release::init(settings, &source, &config.channel, offline).await?;
+        for problem in config.assets.validate(&release) {
+            println!("{}", style(problem).yellow());
+        }
+
+        if dry_run {
+            print_update_plan(name, &config, &release).await;
+            return Ok(());
+        }
+
         if config.name == "Hamster" && host.is_none() {
             println!("需要用 -H 或 --host 指定远程设备的地址，如：-H 192.168.1.108");
             return Ok(());
         }
 
-        // 获取发布信息
         let version = release.clone().version;
         let force = version == config.version;
 
@@ -184,7 +255,7 @@ async fn update(
             .unwrap();
 
         if confirmation {
-            let cache_dir = work_dir().join("_cache");
+            let cache_dir = cache_dir();
 
             // 检测缓存目录中的文件版本
             let info_path = cache_dir.join("version.info");
@@ -192,7 +263,17 @@ async fn update(
             if cache_version != version {
                 if cache_dir.exists() {
                     println!("清理缓存目录...");
-                    fs::remove_dir_all(&cache_dir).unwrap();
+                    for entry in fs::read_dir(&cache_dir)?.filter_map(Result::ok) {
+                        if entry.file_name() == RELEASE_CACHE_DIR {
+                            continue;
+                        }
+
+                        if entry.path().is_dir() {
+                            fs::remove_dir_all(entry.path())?;
+                        } else {
+                            fs::remove_file(entry.path())?;
+                        }
+                    }
                 }
 
                 create_dir_all(&cache_dir)?;
@@ -200,7 +281,22 @@ async fn update(
                 info_file.write_all(version.as_bytes())?;
             }
 
-            match config.name.as_str() {
+            // 安装前检查发布中的配置，文件下载到缓存目录中，安装时直接使用
+            let assets = fetch_assets(&release, &config, None).await;
+            let user_dir = (config.name != "Hamster").then_some(config.user_dir.as_path());
+            if !print_problems(&validate_release(&config, &assets, user_dir)) {
+                let confirmation = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("部署后输入法可能无法正常使用，是否继续更新？")
+                    .default(false)
+                    .interact()
+                    .unwrap();
+                if !confirmation {
+                    return Ok(());
+                }
+            }
+
+            let watch = DeployWatch::start(&config);
+            let snapshot = match config.name.as_str() {
                 #[cfg(target_os = "macos")]
                 "Squirrel" => Squirrel::new(config.clone()).update(release.clone()).await,
 
@@ -215,11 +311,30 @@ async fn update(
                 }
                 #[cfg(target_os = "windows")]
                 "Weasel" => Weasel::new(config.clone()).update(release.clone()).await,
-                _ => println!("不支持该输入法下声笔的安装: {name}"),
+                _ => Err(format!("不支持该输入法下声笔的安装: {name}")),
+            };
+            let snapshot = match snapshot {
+                Ok(snapshot) => snapshot,
+                Err(error) => {
+                    println!("{error}");
+                    println!("{}", style(format!("更新到 {version} 失败")).red());
+                    return Ok(());
+                }
+            };
+
+            // 部署失败时不记录新版本，下次仍会提示更新
+            if config.name != "Hamster" && !watch.wait(&config).await.print_result() {
+                println!("{}", style(format!("更新到 {version} 失败")).red());
+                offer_rollback(&config, host, snapshot).await;
+                return Ok(());
             }
 
             let mut new_config = config.clone();
             new_config.save(&version);
+
+            if config.sync {
+                sync_run(name, host).await;
+            }
         }
     } else {
         println!("指定的设备不存在：{name}");
@@ -228,237 +343,1913 @@ async fn update(
     Ok(())
 }
 
-async fn restore(name: &str, host: Option<&String>) {
-    if let Ok(Some(config)) = IMUpdateConfig::new(name) {
-        if config.name == "Hamster" && host.is_none() {
-            println!("需要用 -H 或 --host 指定远程设备的地址，如：-H 192.168.1.108");
-            return;
-        }
-
-        let backup_path = config.update_dir.join("backups");
-        println!("backup_path={:?}", backup_path);
-        let mut backups: Vec<fs::DirEntry> = fs::read_dir(&backup_path)
-            .unwrap()
-            .filter_map(Result::ok)
-            .collect();
-        backups.sort_by_key(|x| x.file_name());
-
-        let selections: Vec<String> = backups
-            .iter()
-            .map(|e| {
-                return e.file_name().to_str().unwrap().to_string();
-            })
-            .collect();
-        let selected = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("选择要恢复的版本")
-            .default(selections.len() - 1)
-            .items(&selections)
-            .interact()
-            .unwrap();
+/// 检查设备将要安装的发布中的配置，`local` 为 true 时只检查设备上现有的配置，有问题时以非零状态退出
+async fn validate(
+    settings: &Settings,
+    name: &str,
+    host: Option<&String>,
+    offline: bool,
+    local: bool,
+) {
+    let Ok(Some(config)) = IMUpdateConfig::new(name) else {
+        println!("未找到设备 {name} 的配置信息");
+        return;
+    };
 
-        let confirmation = Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(format!("确认要恢复到 {} 版本吗？", selections[selected]))
-            .default(false)
-            .interact()
-            .unwrap();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let user_dir = if config.name != "Hamster" {
+        Some(config.user_dir.clone())
+    } else if host.is_some() || local {
+        let Some(dir) = download_hamster_rime(&config, host, temp_dir.path()).await else {
+            return;
+        };
+        Some(dir)
+    } else {
+        println!("没有指定设备地址，只检查发布中的文件及启用的补丁集");
+        None
+    };
 
-        if confirmation {
-            match config.name.as_str() {
-                #[cfg(target_os = "macos")]
-                "Squirrel" => {
-                    Squirrel::new(config.clone())
-                        .restore(&backups[selected].path())
-                        .await
+    let problems = match (local, user_dir) {
+        (true, Some(dir)) => validate_user_dir(&config, &dir),
+        (_, user_dir) => {
+            let source = config.source.or(&settings.release);
+            let release = match Release::init(settings, &source, &config.channel, offline).await {
+                Ok(release) => release,
+                Err(error) => {
+                    println!("获取发布信息失败：{error}");
+                    return;
                 }
+            };
+            println!("检查版本 {} 中的配置", style(&release.version).cyan());
+            let asset_dir = tempfile::tempdir().unwrap();
+            let assets = fetch_assets(&release, &config, Some(asset_dir.path())).await;
+            validate_release(&config, &assets, user_dir.as_deref())
+        }
+    };
+    if !print_problems(&problems) {
+        std::process::exit(1);
+    }
+}
 
-                #[cfg(target_os = "macos")]
-                "Fcitx5" => {
-                    Fcitx5::new(config.clone())
-                        .restore(&backups[selected].path())
-                        .await
-                }
-                "Hamster" => {
-                    Hamster::new(config.clone(), host.unwrap().clone())
-                        .restore(&backups[selected].path())
-                        .await
-                }
+/// 诊断运行环境，逐项显示检查结果及解决建议，有错误时以非零状态退出
+async fn doctor(settings: &Settings, host: Option<&String>) {
+    let mut report = Report::default();
+    report.section("设置");
+    doctor::check_settings(&mut report);
 
-                #[cfg(target_os = "windows")]
-                "Weasel" => {
-                    Weasel::new(config.clone())
-                        .restore(&backups[selected].path())
-                        .await
+    report.section("输入法");
+    detect_input_methods(&mut report);
+
+    let mut sources = vec![settings.release.clone()];
+    for name in device_names() {
+        report.section(&format!("设备 {name}"));
+        match IMUpdateConfig::new(&name) {
+            Ok(Some(config)) => {
+                doctor::check_device(&mut report, &config, host).await;
+                let source = config.source.or(&settings.release);
+                if !sources.contains(&source) {
+                    sources.push(source);
                 }
-                _ => println!("不支持该输入法下声笔的还原操作: {name}"),
             }
-
-            let mut new_config = config.clone();
-            new_config.save(&selections[selected]);
+            Ok(None) => report.warn("没有设备配置"),
+            Err(error) => {
+                report.error(format!("解析设备配置失败：{error}"));
+                report.suggest(format!(
+                    "修正 {} 中的错误，或通过 device remove 删除设备后重新添加",
+                    work_dir().join(&name).join("config.toml").display()
+                ));
+            }
         }
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let name_arg = Arg::new("name").default_value(OS).help("设备唯一名称");
-    let host_arg = Arg::new("host")
-        .long("host")
-        .short('H')
-        .help("远程设备地址");
+    report.section("发布来源");
+    doctor::check_sources(&mut report, settings, &sources).await;
 
-    let mut device_command = Command::new("device")
-        .about("设备管理")
-        .subcommand(
-            Command::new("list")
-                .about("显示设备列表")
-                .disable_help_flag(true),
-        )
-        .subcommand(
-            Command::new("add")
-                .about("添加远程设备")
-                .disable_help_flag(true)
-                .arg(name_arg.clone()),
-        )
-        .subcommand(
-            Command::new("edit")
-                .about("编辑设备信息")
-                .disable_help_flag(true)
-                .arg(name_arg.clone()),
-        )
-        .subcommand(
-            Command::new("show")
-                .about("显示设备明细")
-                .disable_help_flag(true)
-                .arg(name_arg.clone()),
-        )
-        .subcommand(
-            Command::new("remove")
-                .about("移除远程设备")
-                .disable_help_flag(true)
-                .arg(name_arg.clone()),
-        );
+    report.section("缓存");
+    doctor::check_cache(&mut report);
 
-    if OS == "macos" {
-        device_command = device_command.clone().subcommand(
-            Command::new("default")
-                .about("设置默认设备")
-                .disable_help_flag(true)
-                .arg(name_arg.clone()),
-        );
+    if !report.print_summary() {
+        std::process::exit(1);
     }
+}
 
-    let m = clap::command!()
-        .flatten_help(true)
-        .subcommand(&device_command)
-        .subcommand(
-            Command::new("update")
-                .about("升级词声笔输入法词库")
-                .disable_help_flag(true)
-                .arg(host_arg.clone())
-                .arg(name_arg.clone()),
-        )
-        .subcommand(
-            Command::new("restore")
-                .about("还原到某个备份版本")
-                .disable_help_flag(true)
-                .arg(host_arg.clone())
-                .arg(name_arg.clone()),
-        )
-        .subcommand(
-            Command::new("clean")
-                .about("清理工作目录缓存")
-                .disable_help_flag(true)
-                .arg(
-                    Arg::new("all")
-                        .long("all")
-                        .short('a')
-                        .help("删除整个工作目录，包含设备及备份")
-                        .action(ArgAction::SetTrue),
-                ),
-        )
-        .get_matches();
+async fn check(settings: &Settings, offline: bool) {
+    let configs: Vec<(String, IMUpdateConfig)> = device_names()
+        .into_iter()
+        .filter_map(|name| match IMUpdateConfig::new(&name) {
+            Ok(Some(config)) => Some((name, config)),
+            _ => None,
+        })
+        .collect();
 
-    match m.subcommand() {
-        Some(("device", matches)) => match matches.subcommand() {
-            Some(("list", _)) => {
-                if let Ok(entries) = fs::read_dir(work_dir()) {
-                    let mut entries = entries;
-                    while let Some(Ok(entry)) = entries.next() {
-                        let name = entry.file_name().into_string().unwrap();
-                        if name.starts_with('_') {
-                            continue;
-                        }
+    // 每个来源都列出正式及预发布通道，以及设备使用的其它通道
+    let mut channels: Vec<(ReleaseSource, Channel)> = vec![];
+    for (_, config) in &configs {
+        let source = config.source.or(&settings.release);
+        for channel in [Channel::Stable, Channel::Prerelease, config.channel.clone()] {
+            if !channels.contains(&(source.clone(), channel.clone())) {
+                channels.push((source.clone(), channel));
+            }
+        }
+    }
 
-                        let tic = if name == OS { "->" } else { "  " };
-                        println!("{} {}", tic, entry.file_name().to_str().unwrap());
-                    }
-                }
+    let mut releases = HashMap::new();
+    let mut last_source = None;
+    for (source, channel) in channels {
+        if last_source.as_ref() != Some(&source) {
+            println!("{source}");
+            last_source = Some(source.clone());
+        }
+
+        match Release::init(settings, &source, &channel, offline).await {
+            Ok(release) => {
+                println!(
+                    "  {:<12} {}",
+                    channel.to_string(),
+                    style(&release.version).cyan()
+                );
+                releases.insert((source, channel), release);
             }
-            Some(("add", add_matches)) => {
-                let name = add_matches.get_one::<String>("name").unwrap();
-                let mut config = Hamster::default_config(name);
-                config.write_config();
-                println!("添加完成，配置位于：{}", config.update_dir.display());
+            Err(error) => println!("  {:<12} {}", channel.to_string(), style(error).red()),
+        }
+    }
+
+    println!();
+    for (name, config) in configs {
+        let source = config.source.or(&settings.release);
+        let tic = if name == OS { "->" } else { "  " };
+        let state = match releases.get(&(source, config.channel.clone())) {
+            Some(release) if release.version == config.version => {
+                style("已是最新".to_string()).green()
             }
-            Some(("remove", remove_matches)) => {
-                let name = remove_matches.get_one::<String>("name").unwrap();
-                let confirmation = Confirm::new()
-                    .with_prompt("备份内容已将被删除，且不可恢复, 确认要删除整个工作目录吗？")
-                    .default(false)
-                    .interact()
-                    .unwrap();
+            Some(release) => style(format!("可更新到 {}", release.version)).yellow(),
+            None => style("未知".to_string()).red(),
+        };
+        println!(
+            "{tic} {name:<12} {:<10} {:<12} {state}",
+            config.version,
+            config.channel.to_string()
+        );
+    }
+}
 
-                if confirmation {
-                    let dir = work_dir().join(name);
-                    if dir.exists() {
-                        fs::remove_dir_all(dir).unwrap();
-                    }
+/// 还原备份，指定 `only` 或 `pick` 时只还原选中的文件，其它文件保持不变，`undo` 时撤销上次还原
+async fn restore(
+    name: &str,
+    host: Option<&String>,
+    dry_run: bool,
+    only: &[String],
+    pick: bool,
+    undo: bool,
+) {
+    if let Ok(Some(config)) = IMUpdateConfig::new(name) {
+        if config.name == "Hamster" && host.is_none() && !dry_run {
+            println!("需要用 -H 或 --host 指定远程设备的地址，如：-H 192.168.1.108");
+            return;
+        }
 
-                    println!("设备 {name} 的配置已移除");
-                }
-            }
-            Some(("edit", edit_matches)) => {
-                let name = edit_matches.get_one::<String>("name").unwrap();
-                let config_path = work_dir().join(name).join("config.toml");
-                open(config_path);
+        let store = if dry_run {
+            BackupStore::open_read_only(&config)
+        } else {
+            BackupStore::open(&config)
+        };
+        let store = match store {
+            Ok(store) => store,
+            Err(error) => {
+                println!("打开备份目录失败：{error}");
+                return;
             }
-            Some(("show", show_matches)) => {
-                let name = show_matches.get_one::<String>("name").unwrap();
-                let config_path = work_dir().join(name).join("config.toml");
-                if config_path.exists() {
-                    match fs::read_to_string(config_path) {
-                        Ok(content) => println!("{content}"),
-                        Err(error) => println!("未找到设备 {name} 的配置信息: {error}"),
-                    }
+        };
+        let snapshots = store.snapshots();
+        if snapshots.is_empty() {
+            println!("没有可以还原的备份");
+            return;
+        }
+
+        let mut snapshot = if undo {
+            // 撤销上次还原，即还原到最近一次还原前的备份
+            match snapshots
+                .iter()
+                .rev()
+                .find(|x| x.reason == BackupReason::Restore)
+            {
+                Some(snapshot) => snapshot.clone(),
+                None => {
+                    println!("没有可以撤销的还原");
+                    return;
                 }
             }
+        } else {
+            let selections: Vec<String> = snapshots.iter().map(|x| x.description()).collect();
+            let selected = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("选择要恢复的备份")
+                .default(selections.len() - 1)
+                .items(&selections)
+                .interact()
+                .unwrap();
+            snapshots[selected].clone()
+        };
 
-            #[cfg(target_os = "macos")]
-            Some(("default", default_matches)) => {
-                let name = default_matches.get_one::<String>("name").unwrap();
-                if let Ok(Some(config)) = IMUpdateConfig::new(name) {
-                    config.make_default();
-
-                    println!("已将 {name} 设置为默认设备");
+        let partial = !only.is_empty() || pick;
+        if !only.is_empty() {
+            snapshot = match snapshot.filter(only) {
+                Ok(snapshot) => snapshot,
+                Err(error) => {
+                    println!("无效的文件规则：{error}");
+                    return;
                 }
+            };
+        }
+        if pick {
+            let paths: Vec<&str> = snapshot.files.iter().map(|x| x.path.as_str()).collect();
+            let chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+                .with_prompt("选择要还原的文件（空格选择，回车确认）")
+                .items(&paths)
+                .interact()
+                .unwrap();
+            snapshot.files = chosen
+                .into_iter()
+                .map(|i| snapshot.files[i].clone())
+                .collect();
+        }
+        if partial && snapshot.files.is_empty() {
+            println!("没有要还原的文件");
+            return;
+        }
+        let snapshot = &snapshot;
+
+        if dry_run {
+            print_restore_plan(name, &config, snapshot, partial);
+            return;
+        }
+
+        let prompt = if undo {
+            format!("确认要撤销上次还原，恢复到 {} 吗？", snapshot.id)
+        } else if partial {
+            format!(
+                "确认要从 {} 还原 {} 个文件吗？",
+                snapshot.id,
+                snapshot.files.len()
+            )
+        } else {
+            format!("确认要恢复到 {} 吗？", snapshot.id)
+        };
+        let confirmation = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .default(false)
+            .interact()
+            .unwrap();
+
+        if confirmation {
+            // 先备份当前的状态，选错备份时可以通过 --undo 撤销
+            println!("还原前备份当前状态");
+            if backup_device(&config, host, BackupReason::Restore, None)
+                .await
+                .is_none()
+            {
+                println!("备份当前状态失败，已取消还原");
+                return;
             }
 
-            _ => {
-                println!("不支持的命令");
+            let watch = DeployWatch::start(&config);
+            restore_device(&config, host, snapshot, partial).await;
+            if config.name != "Hamster" && !watch.wait(&config).await.print_result() {
+                println!("可以通过 restore --undo 撤销本次还原");
             }
-        },
-        Some(("update", matches)) => {
-            let name = matches.get_one::<String>("name").unwrap();
-            let host = matches.try_get_one::<String>("host").unwrap();
-            let release = Release::init().await?;
-            if let Err(error) = update(release, name, host).await {
-                eprintln!("更新失败：{}", error)
+
+            // 只还原部分文件时版本不变
+            if !partial {
+                let mut new_config = config.clone();
+                new_config.save(&snapshot.version);
             }
         }
-        Some(("restore", matches)) => {
-            let name = matches.get_one::<String>("name").unwrap();
+    }
+}
+
+/// 将设备还原到备份，本地输入法还原后会重新部署
+async fn restore_device(
+    config: &IMUpdateConfig,
+    host: Option<&String>,
+    snapshot: &Snapshot,
+    partial: bool,
+) {
+    let config = config.clone();
+    match config.name.as_str() {
+        #[cfg(target_os = "macos")]
+        "Squirrel" => Squirrel::new(config).restore(snapshot, partial).await,
+
+        #[cfg(target_os = "macos")]
+        "Fcitx5" => Fcitx5::new(config).restore(snapshot, partial).await,
+        "Hamster" => {
+            Hamster::new(config, host.unwrap().clone())
+                .restore(snapshot, partial)
+                .await
+        }
+
+        #[cfg(target_os = "windows")]
+        "Weasel" => Weasel::new(config).restore(snapshot, partial).await,
+        _ => println!("不支持该输入法下声笔的还原操作: {}", config.name),
+    }
+}
+
+/// 更新后部署失败时还原到本次更新前创建的备份
+async fn offer_rollback(
+    config: &IMUpdateConfig,
+    host: Option<&String>,
+    snapshot: Option<Snapshot>,
+) {
+    let Some(snapshot) = snapshot else {
+        println!("本次更新前没有创建备份，无法回滚");
+        return;
+    };
+
+    let confirmation = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!(
+            "是否还原到更新前的备份 {}？",
+            snapshot.description()
+        ))
+        .default(true)
+        .interact()
+        .unwrap();
+    if !confirmation {
+        println!("可以稍后通过 restore 命令选择备份 {} 还原", snapshot.id);
+        return;
+    }
+
+    let watch = DeployWatch::start(config);
+    restore_device(config, host, &snapshot, false).await;
+    watch.wait(config).await.print_result();
+}
+
+/// 备份设备当前的 Rime 用户目录，返回创建的备份
+async fn backup_device(
+    config: &IMUpdateConfig,
+    host: Option<&String>,
+    reason: BackupReason,
+    label: Option<String>,
+) -> Option<Snapshot> {
+    let config = config.clone();
+    match config.name.as_str() {
+        #[cfg(target_os = "macos")]
+        "Squirrel" => Squirrel::new(config).backup(reason, label).await,
+
+        #[cfg(target_os = "macos")]
+        "Fcitx5" => Fcitx5::new(config).backup(reason, label).await,
+        "Hamster" => {
+            let Some(host) = host else {
+                println!("需要用 -H 或 --host 指定远程设备的地址，如：-H 192.168.1.108");
+                return None;
+            };
+            Hamster::new(config, host.clone())
+                .backup(reason, label)
+                .await
+        }
+
+        #[cfg(target_os = "windows")]
+        "Weasel" => Weasel::new(config).backup(reason, label).await,
+        _ => {
+            println!("不支持该输入法下声笔的备份操作: {}", config.name);
+            None
+        }
+    }
+}
+
+/// 手动备份，不受最大备份数量的限制
+async fn backup_create(name: &str, host: Option<&String>, label: Option<String>) {
+    let Ok(Some(config)) = IMUpdateConfig::new(name) else {
+        println!("未找到设备 {name} 的配置信息");
+        return;
+    };
+
+    backup_device(&config, host, BackupReason::Manual, label).await;
+}
+
+/// 打开设备的备份仓库，失败时输出原因，`read_only` 时不修改磁盘，用于 --dry-run
+fn open_backup_store(name: &str, read_only: bool) -> Option<(IMUpdateConfig, BackupStore)> {
+    let Ok(Some(config)) = IMUpdateConfig::new(name) else {
+        println!("未找到设备 {name} 的配置信息");
+        return None;
+    };
+
+    let store = if read_only {
+        BackupStore::open_read_only(&config)
+    } else {
+        BackupStore::open(&config)
+    };
+    match store {
+        Ok(store) => Some((config, store)),
+        Err(error) => {
+            println!("打开备份目录失败：{error}");
+            None
+        }
+    }
+}
+
+fn backup_list(name: &str) {
+    let Some((_, store)) = open_backup_store(name, false) else {
+        return;
+    };
+
+    let snapshots = store.snapshots();
+    println!(
+        "设备 {name} 共有 {} 个备份，占用 {}",
+        snapshots.len(),
+        HumanBytes(store.disk_usage())
+    );
+    for snapshot in snapshots {
+        let mut reason = snapshot.reason.to_string();
+        if let Some(label) = &snapshot.label {
+            reason.push_str(&format!("：{label}"));
+        }
+        let pinned = if snapshot.pinned { "📌" } else { "  " };
+        println!(
+            "{pinned} {:<28} {:<24} {:<10} {:>10}  {reason}",
+            style(&snapshot.id).cyan().to_string(),
+            snapshot.time(),
+            snapshot.version,
+            HumanBytes(snapshot.size).to_string(),
+        );
+    }
+}
+
+fn backup_show(name: &str, id: &str) {
+    let Some((_, store)) = open_backup_store(name, false) else {
+        return;
+    };
+
+    let snapshot = match store.get(id) {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            println!("{error}");
+            return;
+        }
+    };
+
+    println!("id：{}", style(&snapshot.id).cyan());
+    println!("时间：{}", snapshot.time());
+    println!("版本：{}", snapshot.version);
+    println!("设备：{}", snapshot.device);
+    println!("原因：{}", snapshot.reason);
+    if let Some(label) = &snapshot.label {
+        println!("说明：{label}");
+    }
+    println!("固定：{}", if snapshot.pinned { "是" } else { "否" });
+    println!(
+        "文件：{} 个，共 {}",
+        snapshot.files.len(),
+        HumanBytes(snapshot.size)
+    );
+
+    // 按目录层级缩进显示
+    let mut current: Vec<&str> = vec![];
+    for entry in &snapshot.files {
+        let mut parts: Vec<&str> = entry.path.split('/').collect();
+        let file_name = parts.pop().unwrap_or_default();
+        let common = current
+            .iter()
+            .zip(&parts)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, dir) in parts.iter().enumerate().skip(common) {
+            println!("{}{}/", "  ".repeat(depth + 1), style(dir).blue());
+        }
+        println!(
+            "{}{file_name} ({})",
+            "  ".repeat(parts.len() + 1),
+            HumanBytes(entry.size)
+        );
+        current = parts;
+    }
+}
+
+fn backup_delete(name: &str, id: &str) {
+    let Some((_, store)) = open_backup_store(name, false) else {
+        return;
+    };
+
+    let snapshot = match store.get(id) {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            println!("{error}");
+            return;
+        }
+    };
+
+    let prompt = if snapshot.pinned {
+        format!("备份 {id} 已被固定，确认要删除吗？")
+    } else {
+        format!("确认要删除备份 {id} 吗？")
+    };
+    let confirmation = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+        .unwrap();
+    if !confirmation {
+        return;
+    }
+
+    match store.delete(id) {
+        Ok(_) => println!("备份 {id} 已删除"),
+        Err(error) => println!("删除备份失败：{error}"),
+    }
+}
+
+fn backup_verify(name: &str, id: &str) {
+    let Some((_, store)) = open_backup_store(name, false) else {
+        return;
+    };
+
+    let snapshot = match store.get(id) {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            println!("{error}");
+            return;
+        }
+    };
+
+    let pb = ProgressBar::new(snapshot.files.len() as u64);
+    pb.set_style(get_spinner_style());
+    pb.set_prefix("检查");
+    let problems = store.verify(&snapshot, |path| {
+        pb.set_message(path.to_string());
+        pb.inc(1);
+    });
+    pb.finish_and_clear();
+
+    if problems.is_empty() {
+        println!("备份 {id} 完整，共 {} 个文件", snapshot.files.len());
+        return;
+    }
+
+    println!("备份 {id} 有 {} 个文件存在问题：", problems.len());
+    for problem in problems {
+        println!("  {} {problem}", style("x").red());
+    }
+    std::process::exit(1);
+}
+
+/// 下载仓输入法设备上的 Rime 目录并解压到 `temp_dir`，返回解压后的 Rime 目录
+async fn download_hamster_rime(
+    config: &IMUpdateConfig,
+    host: Option<&String>,
+    temp_dir: &Path,
+) -> Option<PathBuf> {
+    let Some(host) = host else {
+        println!("需要用 -H 或 --host 指定远程设备的地址，如：-H 192.168.1.108");
+        return None;
+    };
+
+    let zip_path = temp_dir.join("Rime.zip");
+    let hamster = Hamster::new(config.clone(), host.clone());
+    if let Err(error) = hamster.download_rime(&zip_path).await {
+        println!("下载设备上的 Rime 目录失败：{error}");
+        return None;
+    }
+    if let Err(error) = zip::ZipArchive::new(fs::File::open(&zip_path).unwrap())
+        .and_then(|mut archive| archive.extract(temp_dir))
+    {
+        println!("解压设备上的 Rime 目录失败：{error}");
+        return None;
+    }
+    Some(temp_dir.join("Rime"))
+}
+
+/// 比较备份，`other` 为 live 时与当前的 Rime 用户目录比较，仓输入法需要先下载设备上的 Rime 目录
+async fn backup_diff(name: &str, host: Option<&String>, id: &str, other: &str) {
+    let Some((config, store)) = open_backup_store(name, false) else {
+        return;
+    };
+
+    let old = match store.get(id) {
+        Ok(snapshot) => Tree::Snapshot(snapshot),
+        Err(error) => {
+            println!("{error}");
+            return;
+        }
+    };
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let new = if other != "live" {
+        match store.get(other) {
+            Ok(snapshot) => Tree::Snapshot(snapshot),
+            Err(error) => {
+                println!("{error}");
+                return;
+            }
+        }
+    } else if config.name == "Hamster" {
+        let Some(dir) = download_hamster_rime(&config, host, temp_dir.path()).await else {
+            return;
+        };
+        Tree::Dir(dir)
+    } else {
+        Tree::Dir(config.user_dir.clone())
+    };
+
+    if let Err(error) = print_diff(&store, &old, &new) {
+        println!("比较失败：{error}");
+    }
+}
+
+/// 按设备的保留策略清理备份
+fn backup_prune(name: &str, dry_run: bool) {
+    let Some((config, store)) = open_backup_store(name, dry_run) else {
+        return;
+    };
+
+    let snapshots = store.to_prune(&config, None);
+    if snapshots.is_empty() {
+        println!("没有需要清理的备份");
+        return;
+    }
+
+    if dry_run {
+        println!("将要删除 {} 个备份：", snapshots.len());
+        for snapshot in &snapshots {
+            println!("  {} {}", style("-").red(), snapshot.description());
+        }
+        return;
+    }
+
+    match store.prune(&config) {
+        Ok(snapshots) => {
+            for snapshot in snapshots {
+                println!("删除备份 {}", snapshot.description());
+            }
+        }
+        Err(error) => println!("清理备份失败：{error}"),
+    }
+}
+
+fn backup_pin(name: &str, id: &str, pinned: bool) {
+    let Some((_, store)) = open_backup_store(name, false) else {
+        return;
+    };
+
+    match store.set_pinned(id, pinned) {
+        Ok(_) if pinned => println!("已固定备份 {id}，它不会被自动清理"),
+        Ok(_) => println!("已取消固定备份 {id}"),
+        Err(error) => println!("{error}"),
+    }
+}
+
+/// 将备份导出为压缩包，用于在设备之间迁移
+fn backup_export(name: &str, id: &str, output: &Path) {
+    let Some((_, store)) = open_backup_store(name, false) else {
+        return;
+    };
+
+    let snapshot = match store.get(id) {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            println!("{error}");
+            return;
+        }
+    };
+
+    match store.export(&snapshot, output) {
+        Ok(_) => println!(
+            "已将备份 {id} 导出到 {}，共 {} 个文件",
+            output.display(),
+            snapshot.files.len()
+        ),
+        Err(error) => println!("导出备份失败：{error}"),
+    }
+}
+
+/// 导入由 backup export 导出的压缩包
+fn backup_import(name: &str, file: &Path) {
+    let Some((_, store)) = open_backup_store(name, false) else {
+        return;
+    };
+
+    match store.import(file) {
+        Ok(snapshot) => {
+            println!(
+                "已导入为备份 {}（来自设备 {}，版本 {}，{} 个文件）",
+                style(&snapshot.id).cyan(),
+                snapshot.device,
+                snapshot.version,
+                snapshot.files.len()
+            );
+            println!("可以通过 restore {name} 选择该备份进行还原");
+        }
+        Err(error) => println!("导入备份失败：{error}"),
+    }
+}
+
+/// 合并多个设备的用户词库并写回各设备的同步目录，输入法同步用户数据时导入
+///
+/// 每个仓输入法设备需要各自的地址，`hosts` 按设备在 `names` 中的顺序对应
+async fn userdb_merge(names: &[String], hosts: &[String], dry_run: bool) {
+    let mut configs = vec![];
+    for name in names {
+        let Ok(Some(config)) = IMUpdateConfig::new(name) else {
+            println!("未找到设备 {name} 的配置信息");
+            return;
+        };
+        configs.push((name, config));
+    }
+    let hamsters = configs.iter().filter(|(_, x)| x.name == "Hamster").count();
+    if hamsters != hosts.len() {
+        println!(
+            "有 {hamsters} 个仓输入法设备，但指定了 {} 个地址，需要按设备的顺序为每个仓输入法设备用 -H 指定地址，如：-H 192.168.1.108 -H 192.168.1.109",
+            hosts.len()
+        );
+        return;
+    }
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut hosts = hosts.iter();
+    let mut devices = vec![];
+    for (i, (name, config)) in configs.into_iter().enumerate() {
+        // 通过 Wi-Fi 只能访问仓输入法的 Rime 目录，因此总是使用其中的 sync 目录
+        let (host, dir, sync_dir) = if config.name == "Hamster" {
+            let host = hosts.next();
+            let temp = temp_dir.path().join(i.to_string());
+            let Some(dir) = download_hamster_rime(&config, host, &temp).await else {
+                return;
+            };
+            let sync_dir = dir.join("sync");
+            (host, dir, sync_dir)
+        } else {
+            userdb::backup_with_dict_manager(&config);
+            let installation = Installation::read(&config.user_dir).unwrap_or_default();
+            let sync_dir = installation.sync_dir(&config.user_dir);
+            (None, config.user_dir.clone(), sync_dir)
+        };
+
+        let userdbs = userdb::read_userdbs(&dir, &sync_dir);
+        if userdbs.is_empty() {
+            println!("设备 {name} 中没有用户词库的文本快照，请先在输入法中执行「同步用户数据」");
+        }
+        devices.push((name, host, sync_dir, userdbs));
+    }
+
+    let mut merged: BTreeMap<String, UserDb> = BTreeMap::new();
+    for (_, _, _, userdbs) in &devices {
+        for userdb in userdbs.values() {
+            match merged.get_mut(&userdb.name) {
+                Some(x) => x.merge(userdb),
+                None => {
+                    merged.insert(userdb.name.clone(), userdb.clone());
+                }
+            }
+        }
+    }
+    if merged.is_empty() {
+        println!("没有可以合并的用户词库");
+        return;
+    }
+
+    for (db_name, userdb) in &merged {
+        let counts: Vec<String> = devices
+            .iter()
+            .map(|(name, _, _, userdbs)| {
+                let count = userdbs.get(db_name).map(UserDb::len).unwrap_or_default();
+                format!("{name} {count} 条")
+            })
+            .collect();
+        println!(
+            "{}：{}，合并后 {} 条",
+            style(db_name).cyan(),
+            counts.join("，"),
+            userdb.len()
+        );
+    }
+    if dry_run {
+        return;
+    }
+
+    for (name, host, sync_dir, _) in &devices {
+        let files = match userdb::write_userdbs(sync_dir, &merged) {
+            Ok(files) => files,
+            Err(error) => {
+                println!("写入设备 {name} 的用户词库失败：{error}");
+                continue;
+            }
+        };
+
+        if let Some(host) = host {
+            // 只上传合并后的快照，设备上的其它文件不受影响
+            let upload_dir = temp_dir.path().join("upload");
+            let sync_dir = upload_dir.join("sync").join(userdb::MERGE_DIR);
+            fs::create_dir_all(&sync_dir).unwrap();
+            for file in files {
+                fs::copy(&file, sync_dir.join(file.file_name().unwrap())).unwrap();
+            }
+
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(get_spinner_style());
+            pb.set_prefix("上传");
+            if let Err(error) = upload_to_ios(&upload_dir, host, &pb).await {
+                println!("上传到设备 {name} 失败：{error}");
+                continue;
+            }
+            pb.finish_and_clear();
+        }
+        println!("已将合并后的用户词库写入设备 {name}");
+    }
+    println!("请在各设备的输入法中执行「同步用户数据」以导入合并后的词库");
+}
+
+/// 通知输入法同步用户数据
+fn sync_device(config: &IMUpdateConfig, host: Option<&String>) {
+    let config = config.clone();
+    match config.name.as_str() {
+        #[cfg(target_os = "macos")]
+        "Squirrel" => Squirrel::new(config).sync(),
+
+        #[cfg(target_os = "macos")]
+        "Fcitx5" => Fcitx5::new(config).sync(),
+        "Hamster" => Hamster::new(config, host.cloned().unwrap_or_default()).sync(),
+
+        #[cfg(target_os = "windows")]
+        "Weasel" => Weasel::new(config).sync(),
+        _ => println!("不支持该输入法下的同步操作: {}", config.name),
+    }
+}
+
+/// 显示设备的 installation.yaml 中的同步设置及同步目录的情况
+async fn sync_status(name: &str, host: Option<&String>) {
+    let Ok(Some(config)) = IMUpdateConfig::new(name) else {
+        println!("未找到设备 {name} 的配置信息");
+        return;
+    };
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let user_dir = if config.name == "Hamster" {
+        match download_hamster_rime(&config, host, temp_dir.path()).await {
+            Some(dir) => dir,
+            None => return,
+        }
+    } else {
+        config.user_dir.clone()
+    };
+
+    match Installation::read(&user_dir) {
+        Ok(installation) => {
+            println!("{}", style(format!("设备 {name} ({})", config.name)).bold());
+            print_sync_status(&installation, &user_dir);
+        }
+        Err(error) => println!("读取 {INSTALLATION_FILE} 失败：{error}"),
+    }
+}
+
+/// 为设备设置同步目录，`names` 为空时设置所有本地输入法的设备
+///
+/// 没有 installation_id 的设备以设备名称作为 id，避免各设备的同步数据互相覆盖
+async fn sync_setup(dir: &Path, names: &[String], host: Option<&String>) {
+    let names: Vec<String> = if names.is_empty() {
+        device_names()
+            .into_iter()
+            .filter(|name| {
+                matches!(IMUpdateConfig::new(name), Ok(Some(config)) if config.name != "Hamster")
+            })
+            .collect()
+    } else {
+        names.to_vec()
+    };
+    if names.is_empty() {
+        println!("没有可以设置同步目录的设备");
+        return;
+    }
+
+    let mut ids: HashMap<String, String> = HashMap::new();
+    for name in &names {
+        let Ok(Some(config)) = IMUpdateConfig::new(name) else {
+            println!("未找到设备 {name} 的配置信息");
+            continue;
+        };
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let user_dir = if config.name == "Hamster" {
+            // 仓输入法的同步目录是手机上的路径，原样写入
+            match download_hamster_rime(&config, host, temp_dir.path()).await {
+                Some(dir) => dir,
+                None => continue,
+            }
+        } else {
+            if let Err(error) = fs::create_dir_all(dir) {
+                println!("创建同步目录 {} 失败：{error}", dir.display());
+                return;
+            }
+            config.user_dir.clone()
+        };
+
+        let mut installation = match Installation::read(&user_dir) {
+            Ok(installation) => installation,
+            Err(error) => {
+                println!("读取设备 {name} 的 {INSTALLATION_FILE} 失败：{error}");
+                continue;
+            }
+        };
+        let id = match installation.installation_id() {
+            Some(id) => id,
+            None => {
+                installation.set("installation_id", name);
+                name.clone()
+            }
+        };
+        if let Some(other) = ids.insert(id.clone(), name.clone()) {
+            println!(
+                "{}",
+                style(format!(
+                    "设备 {name} 与 {other} 的 installation_id 都是 {id}，同步时会互相覆盖"
+                ))
+                .yellow()
+            );
+        }
+
+        let sync_dir = if config.name == "Hamster" {
+            dir.to_path_buf()
+        } else {
+            dir.canonicalize().unwrap_or(dir.to_path_buf())
+        };
+        installation.set("sync_dir", &sync_dir.to_string_lossy());
+
+        let result = if config.name == "Hamster" {
+            let upload_dir = temp_dir.path().join("upload");
+            fs::create_dir_all(&upload_dir).unwrap();
+            installation.write(&upload_dir).unwrap();
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(get_spinner_style());
+            pb.set_prefix("上传");
+            let result = upload_to_ios(&upload_dir, host.unwrap(), &pb)
+                .await
+                .map_err(|x| x.to_string());
+            pb.finish_and_clear();
+            result
+        } else {
+            installation.write(&user_dir).map_err(|x| x.to_string())
+        };
+        match result {
+            Ok(_) => println!(
+                "设备 {name} 的同步目录已设置为 {}，installation_id 为 {id}",
+                sync_dir.display()
+            ),
+            Err(error) => println!("写入设备 {name} 的 {INSTALLATION_FILE} 失败：{error}"),
+        }
+    }
+    println!("重新部署后生效，之后在各设备上同步用户数据即可共享用户词库");
+}
+
+/// 触发设备同步用户数据，并等待同步目录中出现本设备的数据
+async fn sync_run(name: &str, host: Option<&String>) {
+    let Ok(Some(config)) = IMUpdateConfig::new(name) else {
+        println!("未找到设备 {name} 的配置信息");
+        return;
+    };
+
+    if config.name == "Hamster" {
+        sync_device(&config, host);
+        return;
+    }
+
+    let installation = Installation::read(&config.user_dir).unwrap_or_default();
+    let since = std::time::SystemTime::now();
+    println!("正在同步用户数据...");
+    sync_device(&config, host);
+    if wait_for_sync(&installation, &config.user_dir, since).await {
+        println!("同步完成");
+    } else {
+        println!(
+            "{}",
+            style("没有检测到本设备的同步数据，输入法可能仍在同步或同步失败").yellow()
+        );
+    }
+    print_sync_status(&installation, &config.user_dir);
+}
+
+fn custom_list() {
+    let names = custom::patch_sets();
+    if names.is_empty() {
+        println!("还没有保存任何补丁集，可以通过 custom add 添加");
+        return;
+    }
+
+    let configs: Vec<(String, IMUpdateConfig)> = device_names()
+        .into_iter()
+        .filter_map(|name| match IMUpdateConfig::new(&name) {
+            Ok(Some(config)) => Some((name, config)),
+            _ => None,
+        })
+        .collect();
+    for name in names {
+        let devices: Vec<&str> = configs
+            .iter()
+            .filter(|(_, config)| config.custom.contains(&name))
+            .map(|(device, _)| device.as_str())
+            .collect();
+        let devices = if devices.is_empty() {
+            "未启用".to_string()
+        } else {
+            format!("启用的设备：{}", devices.join("、"))
+        };
+        println!("{}（{devices}）", style(&name).cyan());
+        for file in custom::patch_files(&name).unwrap_or_default().keys() {
+            println!("  {file}");
+        }
+    }
+}
+
+/// 在设备配置中启用或停用补丁集
+fn custom_enable(set: &str, name: &str, enabled: bool) {
+    let Ok(Some(mut config)) = IMUpdateConfig::new(name) else {
+        println!("未找到设备 {name} 的配置信息");
+        return;
+    };
+
+    if enabled {
+        if let Err(error) = custom::patch_files(set) {
+            println!("{error}");
+            return;
+        }
+        if !config.custom.iter().any(|x| x == set) {
+            config.custom.push(set.to_string());
+        }
+        config.write_config();
+        println!("设备 {name} 已启用补丁集 {set}，更新后会自动应用");
+    } else {
+        config.custom.retain(|x| x != set);
+        config.write_config();
+        println!("设备 {name} 已停用补丁集 {set}，已写入设备的文件不会被删除");
+    }
+}
+
+/// 立即将设备启用的补丁写入用户目录或上传到仓输入法
+async fn custom_apply(name: &str, host: Option<&String>) {
+    let Ok(Some(config)) = IMUpdateConfig::new(name) else {
+        println!("未找到设备 {name} 的配置信息");
+        return;
+    };
+    if config.custom.is_empty() {
+        println!("设备 {name} 没有启用任何补丁集");
+        return;
+    }
+
+    if config.name == "Hamster" {
+        let Some(host) = host else {
+            println!("需要用 -H 或 --host 指定远程设备的地址，如：-H 192.168.1.108");
+            return;
+        };
+        Hamster::new(config, host.clone()).upload_patches().await;
+        println!("需要在手机上重新部署");
+    } else {
+        custom::apply_to_user_dir(&config);
+        println!("重新部署后生效");
+    }
+}
+
+/// 比较设备上的补丁文件与保存的补丁集，存在差异时以非零状态退出
+async fn custom_diff(name: &str, host: Option<&String>) {
+    let Ok(Some(config)) = IMUpdateConfig::new(name) else {
+        println!("未找到设备 {name} 的配置信息");
+        return;
+    };
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let dir = if config.name == "Hamster" && !config.custom.is_empty() {
+        match download_hamster_rime(&config, host, temp_dir.path()).await {
+            Some(dir) => dir,
+            None => return,
+        }
+    } else {
+        config.user_dir.clone()
+    };
+
+    println!("设备 {name} 的自定义补丁：");
+    match custom::print_drift(&config, &dir) {
+        Ok(false) => {}
+        Ok(true) => {
+            println!("可以通过 custom apply 覆盖设备上的文件，或通过 custom add 保存设备上的修改");
+            std::process::exit(1);
+        }
+        Err(error) => println!("比较自定义补丁失败：{error}"),
+    }
+}
+
+/// 重新部署本地的输入法并检查部署结果，仓输入法需要在手机上手动部署
+async fn deploy_device(config: &IMUpdateConfig) {
+    let watch = DeployWatch::start(config);
+    let deployed = config.clone();
+    let supported = match deployed.name.as_str() {
+        #[cfg(target_os = "macos")]
+        "Squirrel" => {
+            Squirrel::new(deployed).deploy();
+            true
+        }
+
+        #[cfg(target_os = "macos")]
+        "Fcitx5" => {
+            Fcitx5::new(deployed).deploy();
+            true
+        }
+
+        #[cfg(target_os = "windows")]
+        "Weasel" => {
+            Weasel::new(deployed).deploy();
+            true
+        }
+        "Hamster" => {
+            println!("需要在手机上重新部署");
+            false
+        }
+        _ => {
+            println!("不支持该输入法的部署操作: {}", config.name);
+            false
+        }
+    };
+    if supported {
+        watch.wait(config).await.print_result();
+    }
+}
+
+/// 读取设备上的 sbsrf.custom.yaml，仓输入法需要先下载设备上的 Rime 目录
+async fn read_patch(
+    config: &IMUpdateConfig,
+    host: Option<&String>,
+    temp_dir: &Path,
+) -> Option<(PathBuf, PatchFile)> {
+    let dir = if config.name == "Hamster" {
+        download_hamster_rime(config, host, temp_dir).await?
+    } else {
+        config.user_dir.clone()
+    };
+
+    match PatchFile::read(&dir) {
+        Ok(patch) => Some((dir, patch)),
+        Err(error) => {
+            println!("读取 {PATCH_FILE} 失败：{error}");
+            None
+        }
+    }
+}
+
+/// 显示设备上声笔方案的选项，设备配置与补丁不一致时给出提示
+async fn options_show(name: &str, host: Option<&String>) {
+    let Ok(Some(config)) = IMUpdateConfig::new(name) else {
+        println!("未找到设备 {name} 的配置信息");
+        return;
+    };
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let Some((_, patch)) = read_patch(&config, host, temp_dir.path()).await else {
+        return;
+    };
+
+    let sentence = options::sentence(&patch);
+    let page_size = options::page_size(&patch);
+    println!("sentence：{}", if sentence { "on" } else { "off" });
+    println!(
+        "page_size：{}",
+        page_size.map_or("default".to_string(), |x| x.to_string())
+    );
+    // 设备配置中未开启整句输入或未设置候选数量时，更新不会修改补丁中的值
+    if (config.sentence && !sentence) || config.page_size.is_some_and(|x| page_size != Some(x)) {
+        println!(
+            "{}",
+            style(format!(
+                "{PATCH_FILE} 与设备配置中的选项不一致，更新时将按设备配置重新写入，也可以通过 options set 修改"
+            ))
+            .yellow()
+        );
+    }
+}
+
+/// 修改设备配置中声笔方案的选项，写入设备上的补丁后重新部署
+async fn options_set(name: &str, host: Option<&String>, option: &str, value: &str) {
+    let Ok(Some(mut config)) = IMUpdateConfig::new(name) else {
+        println!("未找到设备 {name} 的配置信息");
+        return;
+    };
+
+    if let Err(error) = options::set_option(&mut config, option, value) {
+        println!("{error}");
+        return;
+    }
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let Some((dir, _)) = read_patch(&config, host, temp_dir.path()).await else {
+        return;
+    };
+    let mut patch = match custom::options_patch(&config, &dir) {
+        Ok(patch) => patch,
+        Err(error) => {
+            println!("读取补丁集失败：{error}");
+            return;
+        }
+    };
+    options::write_option(&mut patch, &config, option);
+
+    if config.name == "Hamster" {
+        let upload_dir = temp_dir.path().join("upload");
+        fs::create_dir_all(&upload_dir).unwrap();
+        patch.write(&upload_dir).unwrap();
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(get_spinner_style());
+        pb.set_prefix("上传");
+        let result = upload_to_ios(&upload_dir, host.unwrap(), &pb).await;
+        pb.finish_and_clear();
+        if let Err(error) = result {
+            println!("上传 {PATCH_FILE} 失败：{error}");
+            return;
+        }
+    } else if let Err(error) = patch.write(&dir) {
+        println!("写入 {PATCH_FILE} 失败：{error}");
+        return;
+    }
+    println!("已将 {option} 设置为 {value}");
+
+    config.write_config();
+    if config.sentence && !options::grammar_installed(&dir) {
+        println!("整句输入需要 octagram 语言模型，请执行 update 安装");
+    }
+
+    deploy_device(&config).await;
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let name_arg = Arg::new("name").default_value(OS).help("设备唯一名称");
+    let host_arg = Arg::new("host")
+        .long("host")
+        .short('H')
+        .help("远程设备地址");
+    let dry_run_arg = Arg::new("dry-run")
+        .long("dry-run")
+        .alias("plan")
+        .help("只显示将要进行的操作，不做任何修改")
+        .action(ArgAction::SetTrue);
+
+    let id_arg = Arg::new("id").required(true).help("备份 id");
+    let set_arg = Arg::new("set").required(true).help("补丁集名称");
+    let options_help = options::OPTIONS.iter().fold(
+        "修改方案选项并重新部署：".to_string(),
+        |help, (name, description)| format!("{help}\n  {name}: {description}"),
+    );
+
+    let mut device_command = Command::new("device")
+        .about("设备管理")
+        .subcommand(
+            Command::new("list")
+                .about("显示设备列表")
+                .disable_help_flag(true),
+        )
+        .subcommand(
+            Command::new("add")
+                .about("添加远程设备")
+                .disable_help_flag(true)
+                .arg(name_arg.clone()),
+        )
+        .subcommand(
+            Command::new("edit")
+                .about("编辑设备信息")
+                .disable_help_flag(true)
+                .arg(name_arg.clone()),
+        )
+        .subcommand(
+            Command::new("show")
+                .about("显示设备明细")
+                .disable_help_flag(true)
+                .arg(name_arg.clone()),
+        )
+        .subcommand(
+            Command::new("remove")
+                .about("移除设备的配置及备份")
+                .disable_help_flag(true)
+                .arg(name_arg.clone()),
+        );
+
+    if OS == "macos" {
+        device_command = device_command.clone().subcommand(
+            Command::new("default")
+                .about("设置默认设备")
+                .disable_help_flag(true)
+                .arg(name_arg.clone()),
+        );
+    }
+
+    let m = clap::command!()
+        .flatten_help(true)
+        .arg(
+            Arg::new("work-dir")
+                .long("work-dir")
+                .env("SBSRF_UPDATE_HOME")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("工作目录，默认为 ~/.sbsrf-update，Linux 下遵循 XDG 规范"),
+        )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .global(true)
+                .help("网络请求使用的代理，如 http://127.0.0.1:7890"),
+        )
+        .arg(
+            Arg::new("offline")
+                .long("offline")
+                .global(true)
+                .help("离线模式，使用缓存的发布信息及文件")
+                .action(ArgAction::SetTrue),
+        )
+        .subcommand(&device_command)
+        .subcommand(
+            Command::new("update")
+                .about("升级词声笔输入法词库")
+                .disable_help_flag(true)
+                .arg(host_arg.clone())
+                .arg(dry_run_arg.clone())
+                .arg(name_arg.clone()),
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("检查将要安装的发布及设备上的补丁中的 Rime 配置，有问题时以非零状态退出")
+                .disable_help_flag(true)
+                .arg(host_arg.clone())
+                .arg(
+                    Arg::new("local")
+                        .long("local")
+                        .help("只检查设备上现有的配置")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(name_arg.clone()),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("检查各设备是否有可用的更新，并列出各发布通道的最新版本")
+                .disable_help_flag(true),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("还原到某个备份版本")
+                .disable_help_flag(true)
+                .arg(host_arg.clone())
+                .arg(dry_run_arg.clone())
+                .arg(
+                    Arg::new("only")
+                        .long("only")
+                        .action(ArgAction::Append)
+                        .help("只还原匹配的文件，支持通配符，可以指定多次"),
+                )
+                .arg(
+                    Arg::new("pick")
+                        .long("pick")
+                        .help("从备份中选择要还原的文件")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("undo")
+                        .long("undo")
+                        .help("撤销上次还原，恢复到还原前的状态")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(name_arg.clone()),
+        )
+        .subcommand(
+            Command::new("backup")
+                .about("备份管理")
+                .subcommand(
+                    Command::new("create")
+                        .about("立即备份当前的 Rime 用户目录")
+                        .disable_help_flag(true)
+                        .arg(host_arg.clone())
+                        .arg(
+                            Arg::new("label")
+                                .long("label")
+                                .short('l')
+                                .help("备份的说明，如 \"调整配置前\""),
+                        )
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("列出设备的所有备份")
+                        .disable_help_flag(true)
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("显示备份的信息及其中的文件")
+                        .disable_help_flag(true)
+                        .arg(id_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("删除备份")
+                        .disable_help_flag(true)
+                        .arg(id_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("verify")
+                        .about("检查备份中的文件是否完整")
+                        .disable_help_flag(true)
+                        .arg(id_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("diff")
+                        .about("比较两个备份，或备份与当前的 Rime 用户目录")
+                        .disable_help_flag(true)
+                        .arg(host_arg.clone())
+                        .arg(id_arg.clone())
+                        .arg(
+                            Arg::new("other")
+                                .default_value("live")
+                                .help("另一个备份的 id，live 表示当前的 Rime 用户目录"),
+                        )
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("prune")
+                        .about("按保留策略清理旧的备份")
+                        .disable_help_flag(true)
+                        .arg(dry_run_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("pin")
+                        .about("固定备份，使其不会被自动清理")
+                        .disable_help_flag(true)
+                        .arg(id_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("unpin")
+                        .about("取消固定备份")
+                        .disable_help_flag(true)
+                        .arg(id_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("export")
+                        .about("将备份导出为压缩包，用于迁移到其它设备")
+                        .disable_help_flag(true)
+                        .arg(id_arg.clone())
+                        .arg(
+                            Arg::new("output")
+                                .long("output")
+                                .short('o')
+                                .required(true)
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .help("导出的文件，如 backup.zip"),
+                        )
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("导入由 backup export 导出的压缩包")
+                        .disable_help_flag(true)
+                        .arg(
+                            Arg::new("file")
+                                .required(true)
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .help("导出的压缩包"),
+                        )
+                        .arg(
+                            Arg::new("device")
+                                .long("device")
+                                .short('d')
+                                .default_value(OS)
+                                .help("导入到的设备"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("userdb").about("用户词库管理").subcommand(
+                Command::new("merge")
+                    .about("合并多个设备的用户词库，并写回各设备")
+                    .disable_help_flag(true)
+                    .arg(
+                        host_arg
+                            .clone()
+                            .action(ArgAction::Append)
+                            .help("仓输入法设备的地址，多个时按设备的顺序分别指定"),
+                    )
+                    .arg(dry_run_arg.clone())
+                    .arg(
+                        Arg::new("names")
+                            .required(true)
+                            .num_args(1..)
+                            .help("要合并的设备名称，如 macos ios"),
+                    ),
+            ),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Rime 用户数据同步")
+                .subcommand(
+                    Command::new("status")
+                        .about("显示设备的同步目录及各设备的同步情况")
+                        .disable_help_flag(true)
+                        .arg(host_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("setup")
+                        .about("为设备设置共享的同步目录，如网盘中的文件夹")
+                        .disable_help_flag(true)
+                        .arg(host_arg.clone())
+                        .arg(
+                            Arg::new("dir")
+                                .required(true)
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .help("同步目录"),
+                        )
+                        .arg(
+                            Arg::new("names")
+                                .num_args(0..)
+                                .help("设备名称，默认为所有本地输入法的设备"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("run")
+                        .about("通知输入法同步用户数据")
+                        .disable_help_flag(true)
+                        .arg(host_arg.clone())
+                        .arg(name_arg.clone()),
+                ),
+        )
+        .subcommand(
+            Command::new("custom")
+                .about("自定义补丁（*.custom.yaml）管理")
+                .subcommand(
+                    Command::new("list")
+                        .about("列出保存的补丁集及启用它们的设备")
+                        .disable_help_flag(true),
+                )
+                .subcommand(
+                    Command::new("add")
+                        .about("将补丁文件保存到补丁集中，补丁集不存在时创建")
+                        .disable_help_flag(true)
+                        .arg(set_arg.clone())
+                        .arg(
+                            Arg::new("files")
+                                .required(true)
+                                .num_args(1..)
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .help("补丁文件，如 sbsrf.custom.yaml"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("删除补丁集")
+                        .disable_help_flag(true)
+                        .arg(set_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("enable")
+                        .about("为设备启用补丁集，更新后自动应用")
+                        .disable_help_flag(true)
+                        .arg(set_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("disable")
+                        .about("为设备停用补丁集")
+                        .disable_help_flag(true)
+                        .arg(set_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("apply")
+                        .about("立即将启用的补丁写入设备")
+                        .disable_help_flag(true)
+                        .arg(host_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("diff")
+                        .about("检查设备上的补丁文件是否与保存的补丁集一致")
+                        .disable_help_flag(true)
+                        .arg(host_arg.clone())
+                        .arg(name_arg.clone()),
+                ),
+        )
+        .subcommand(
+            Command::new("options")
+                .about("声笔方案选项")
+                .subcommand(
+                    Command::new("show")
+                        .about("显示设备上的方案选项")
+                        .disable_help_flag(true)
+                        .arg(host_arg.clone())
+                        .arg(name_arg.clone()),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about(options_help)
+                        .disable_help_flag(true)
+                        .arg(host_arg.clone())
+                        .arg(
+                            Arg::new("option")
+                                .required(true)
+                                .value_parser(options::OPTIONS.map(|(name, _)| name))
+                                .help("选项名称"),
+                        )
+                        .arg(Arg::new("value").required(true).help("选项的值"))
+                        .arg(name_arg.clone()),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("诊断运行环境，检查输入法、设备配置、网络及缓存，并给出解决建议")
+                .disable_help_flag(true)
+                .arg(host_arg.clone()),
+        )
+        .subcommand(
+            Command::new("clean")
+                .about("清理工作目录缓存")
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .short('a')
+                        .help("删除整个工作目录，包含设备及备份")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .get_matches();
+
+    if let Err(error) = init_work_dir(m.get_one::<PathBuf>("work-dir").cloned()) {
+        eprintln!("{error}");
+        std::process::exit(1);
+    }
+
+    let settings = match Settings::init() {
+        Ok(settings) => settings,
+        // doctor 会报告设置文件中的错误
+        Err(_) if m.subcommand_name() == Some("doctor") => Settings::current(),
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+    if let Err(error) = init_http_client(&settings.network, m.get_one::<String>("proxy")) {
+        eprintln!("{error}");
+        std::process::exit(1);
+    }
+
+    let offline = m.get_flag("offline");
+    set_offline(offline);
+
+    match m.subcommand() {
+        Some(("device", matches)) => match matches.subcommand() {
+            Some(("list", _)) => {
+                for name in device_names() {
+                    let tic = if name == OS { "->" } else { "  " };
+                    println!("{} {}", tic, name);
+                }
+            }
+            Some(("add", add_matches)) => {
+                let name = add_matches.get_one::<String>("name").unwrap();
+                let mut config = Hamster::default_config(name);
+                config.write_config();
+                println!("添加完成，配置位于：{}", config.update_dir.display());
+            }
+            Some(("remove", remove_matches)) => {
+                let name = remove_matches.get_one::<String>("name").unwrap();
+                let confirmation = Confirm::new()
+                    .with_prompt(format!(
+                        "设备 {name} 的配置及所有备份都将被删除，且不可恢复，确认要删除吗？"
+                    ))
+                    .default(false)
+                    .interact()
+                    .unwrap();
+
+                if confirmation {
+                    // 配置与备份分别位于工作目录及数据目录中，未单独指定数据目录时二者相同
+                    for dir in [work_dir().join(name), data_dir().join(name)] {
+                        if dir.exists() {
+                            if let Err(error) = fs::remove_dir_all(&dir) {
+                                println!("删除 {} 失败：{error}", dir.display());
+                                return Ok(());
+                            }
+                        }
+                    }
+
+                    println!("设备 {name} 的配置及备份已移除");
+                }
+            }
+            Some(("edit", edit_matches)) => {
+                let name = edit_matches.get_one::<String>("name").unwrap();
+                let config_path = work_dir().join(name).join("config.toml");
+                open(config_path);
+            }
+            Some(("show", show_matches)) => {
+                let name = show_matches.get_one::<String>("name").unwrap();
+                let config_path = work_dir().join(name).join("config.toml");
+                if config_path.exists() {
+                    match fs::read_to_string(config_path) {
+                        Ok(content) => println!("{content}"),
+                        Err(error) => println!("未找到设备 {name} 的配置信息: {error}"),
+                    }
+                }
+            }
+
+            #[cfg(target_os = "macos")]
+            Some(("default", default_matches)) => {
+                let name = default_matches.get_one::<String>("name").unwrap();
+                if let Ok(Some(config)) = IMUpdateConfig::new(name) {
+                    config.make_default();
+
+                    println!("已将 {name} 设置为默认设备");
+                }
+            }
+
+            _ => {
+                println!("不支持的命令");
+            }
+        },
+        Some(("update", matches)) => {
+            let name = matches.get_one::<String>("name").unwrap();
             let host = matches.try_get_one::<String>("host").unwrap();
-            restore(name, host).await;
+            let dry_run = matches.get_flag("dry-run");
+            if let Err(error) = update(settings, name, host, offline, dry_run).await {
+                eprintln!("更新失败：{}", error)
+            }
+        }
+        Some(("validate", matches)) => {
+            let name = matches.get_one::<String>("name").unwrap();
+            let host = matches.try_get_one::<String>("host").unwrap();
+            let local = matches.get_flag("local");
+            validate(settings, name, host, offline, local).await;
+        }
+        Some(("check", _)) => check(settings, offline).await,
+        Some(("restore", matches)) => {
+            let name = matches.get_one::<String>("name").unwrap();
+            let host = matches.try_get_one::<String>("host").unwrap();
+            let dry_run = matches.get_flag("dry-run");
+            let only: Vec<String> = matches
+                .get_many::<String>("only")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let pick = matches.get_flag("pick");
+            let undo = matches.get_flag("undo");
+            restore(name, host, dry_run, &only, pick, undo).await;
+        }
+        Some(("backup", matches)) => match matches.subcommand() {
+            Some(("create", matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                let host = matches.try_get_one::<String>("host").unwrap();
+                let label = matches.get_one::<String>("label").cloned();
+                backup_create(name, host, label).await;
+            }
+            Some(("list", matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                backup_list(name);
+            }
+            Some((command @ ("show" | "delete" | "verify"), matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                let id = matches.get_one::<String>("id").unwrap();
+                match command {
+                    "show" => backup_show(name, id),
+                    "delete" => backup_delete(name, id),
+                    _ => backup_verify(name, id),
+                }
+            }
+            Some(("diff", matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                let host = matches.try_get_one::<String>("host").unwrap();
+                let id = matches.get_one::<String>("id").unwrap();
+                let other = matches.get_one::<String>("other").unwrap();
+                backup_diff(name, host, id, other).await;
+            }
+            Some(("prune", matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                backup_prune(name, matches.get_flag("dry-run"));
+            }
+            Some((command @ ("pin" | "unpin"), matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                let id = matches.get_one::<String>("id").unwrap();
+                backup_pin(name, id, command == "pin");
+            }
+            Some(("export", matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                let id = matches.get_one::<String>("id").unwrap();
+                let output = matches.get_one::<PathBuf>("output").unwrap();
+                backup_export(name, id, output);
+            }
+            Some(("import", matches)) => {
+                let name = matches.get_one::<String>("device").unwrap();
+                let file = matches.get_one::<PathBuf>("file").unwrap();
+                backup_import(name, file);
+            }
+            _ => {
+                println!("不支持的命令");
+            }
+        },
+
+        Some(("userdb", matches)) => match matches.subcommand() {
+            Some(("merge", matches)) => {
+                let names: Vec<String> = matches
+                    .get_many::<String>("names")
+                    .unwrap()
+                    .cloned()
+                    .collect();
+                let hosts: Vec<String> = matches
+                    .get_many::<String>("host")
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect();
+                userdb_merge(&names, &hosts, matches.get_flag("dry-run")).await;
+            }
+            _ => {
+                println!("不支持的命令");
+            }
+        },
+
+        Some(("sync", matches)) => match matches.subcommand() {
+            Some((command @ ("status" | "run"), matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                let host = matches.try_get_one::<String>("host").unwrap();
+                if command == "status" {
+                    sync_status(name, host).await;
+                } else {
+                    sync_run(name, host).await;
+                }
+            }
+            Some(("setup", matches)) => {
+                let dir = matches.get_one::<PathBuf>("dir").unwrap();
+                let names: Vec<String> = matches
+                    .get_many::<String>("names")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                let host = matches.try_get_one::<String>("host").unwrap();
+                sync_setup(dir, &names, host).await;
+            }
+            _ => {
+                println!("不支持的命令");
+            }
+        },
+
+        Some(("custom", matches)) => match matches.subcommand() {
+            Some(("list", _)) => custom_list(),
+            Some(("add", matches)) => {
+                let set = matches.get_one::<String>("set").unwrap();
+                let files: Vec<PathBuf> = matches
+                    .get_many::<PathBuf>("files")
+                    .unwrap()
+                    .cloned()
+                    .collect();
+                match custom::save_patch_set(set, &files) {
+                    Ok(saved) => println!("已保存到补丁集 {set}：{}", saved.join("、")),
+                    Err(error) => println!("保存补丁失败：{error}"),
+                }
+            }
+            Some(("remove", matches)) => {
+                let set = matches.get_one::<String>("set").unwrap();
+                match custom::remove_patch_set(set) {
+                    Ok(_) => println!("补丁集 {set} 已删除"),
+                    Err(error) => println!("删除补丁集失败：{error}"),
+                }
+            }
+            Some((command @ ("enable" | "disable"), matches)) => {
+                let set = matches.get_one::<String>("set").unwrap();
+                let name = matches.get_one::<String>("name").unwrap();
+                custom_enable(set, name, command == "enable");
+            }
+            Some((command @ ("apply" | "diff"), matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                let host = matches.try_get_one::<String>("host").unwrap();
+                if command == "apply" {
+                    custom_apply(name, host).await;
+                } else {
+                    custom_diff(name, host).await;
+                }
+            }
+            _ => {
+                println!("不支持的命令");
+            }
+        },
+
+        Some(("options", matches)) => match matches.subcommand() {
+            Some(("show", matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                let host = matches.try_get_one::<String>("host").unwrap();
+                options_show(name, host).await;
+            }
+            Some(("set", matches)) => {
+                let name = matches.get_one::<String>("name").unwrap();
+                let host = matches.try_get_one::<String>("host").unwrap();
+                let option = matches.get_one::<String>("option").unwrap();
+                let value = matches.get_one::<String>("value").unwrap();
+                options_set(name, host, option, value).await;
+            }
+            _ => {
+                println!("不支持的命令");
+            }
+        },
+
+        Some(("doctor", matches)) => {
+            let host = matches.try_get_one::<String>("host").unwrap();
+            doctor(settings, host).await;
         }
 
         Some(("clean", matches)) => {
@@ -470,18 +2261,33 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .interact()
                     .unwrap();
                 if confirmation {
-                    fs::remove_dir_all(work_dir()).unwrap();
+                    for dir in [work_dir(), cache_dir(), data_dir()] {
+                        if dir.exists() {
+                            fs::remove_dir_all(dir).unwrap();
+                        }
+                    }
                 }
             } else {
-                fs::remove_dir_all(work_dir().join("_cache")).unwrap();
+                if cache_dir().exists() {
+                    fs::remove_dir_all(cache_dir()).unwrap();
+                }
                 println!("缓存目录已被清理");
             }
         }
         _ => {
             // 获取发布信息
-            let release = Release::init().await?;
+            let release =
+                match Release::init(settings, &settings.release, &Channel::Stable, offline).await {
+                    Ok(release) => release,
+                    Err(error) => {
+                        eprintln!("{error}");
+                        return Ok(());
+                    }
+                };
             install_if_needed(&release).await;
-            update(release, OS, None).await?;
+            if let Err(error) = update(settings, OS, None, offline, false).await {
+                eprintln!("更新失败：{}", error)
+            }
         }
     }
 
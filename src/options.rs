@@ -0,0 +1,521 @@
+use std::{fs, io, path::Path};
+
+use yaml_rust2::{yaml::Hash, Yaml, YamlEmitter, YamlLoader};
+
+use crate::im::IMUpdateConfig;
+
+/// 声笔方案的补丁文件
+pub const PATCH_FILE: &str = "sbsrf.custom.yaml";
+/// 补丁中候选数量的路径
+const PAGE_SIZE: &str = "menu/page_size";
+/// 整句输入使用的语言模型
+const GRAMMAR: &str = "zh-hans-t-essay-bgw";
+
+/// 可以通过 options 命令设置的方案选项
+pub const OPTIONS: [(&str, &str); 2] = [
+    ("sentence", "整句输入，on 或 off"),
+    ("page_size", "候选数量，1 到 10，default 为方案的默认值"),
+];
+
+/// 补丁文件中 `patch:` 下的 `路径: 值`，按行读写以保留手动添加的其它内容
+///
+/// 路径可以写成以 / 分隔的 `menu/page_size: 6`，也可以是嵌套的 `menu:` 下的 `page_size: 6`，
+/// 或单行的 `menu: {page_size: 6}`，修改单行的写法时会先展开为嵌套的写法
+#[derive(Debug, Clone)]
+pub struct PatchFile {
+    lines: Vec<String>,
+}
+
+/// 一行中的缩进、键及值，空行、注释及列表项返回 None
+fn entry(line: &str) -> Option<(usize, &str, &str)> {
+    let content = line.trim_start();
+    if content.is_empty() || content.starts_with(['#', '-']) {
+        return None;
+    }
+    let (key, value) = content.split_once(':')?;
+    // 去掉行尾的注释
+    let value = value.split_once(" #").map_or(value, |(value, _)| value);
+    Some((
+        line.len() - content.len(),
+        key.trim().trim_matches('"'),
+        value.trim(),
+    ))
+}
+
+/// 值为空，即下面嵌套了其它项
+fn is_nested(value: &str) -> bool {
+    value.is_empty() || value.starts_with('#')
+}
+
+/// 单行的 `{...}` 中的值，所有值都是标量时才支持
+fn flow_mapping(value: &str) -> Option<Hash> {
+    if !value.starts_with('{') {
+        return None;
+    }
+    let doc = YamlLoader::load_from_str(value).ok()?.into_iter().next()?;
+    let Yaml::Hash(hash) = doc else {
+        return None;
+    };
+    let scalars = hash
+        .iter()
+        .all(|(key, value)| scalar(key).is_some() && scalar(value).is_some());
+    scalars.then_some(hash)
+}
+
+fn scalar(yaml: &Yaml) -> Option<String> {
+    match yaml {
+        Yaml::Array(_) | Yaml::Hash(_) | Yaml::Alias(_) | Yaml::BadValue => None,
+        _ => {
+            let mut output = String::new();
+            YamlEmitter::new(&mut output).dump(yaml).ok()?;
+            Some(output.trim_start_matches("---").trim().to_string())
+        }
+    }
+}
+
+impl PatchFile {
+    pub fn parse(content: &str) -> Self {
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        if !lines.iter().any(|x| x.trim_end() == "patch:") {
+            lines.push("patch:".to_string());
+        }
+        Self { lines }
+    }
+
+    /// 读取用户目录中的补丁文件，文件不存在时返回空的补丁
+    pub fn read(dir: &Path) -> io::Result<Self> {
+        match fs::read_to_string(dir.join(PATCH_FILE)) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::parse("")),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn content(&self) -> String {
+        let mut content = self.lines.join("\n");
+        content.push('\n');
+        content
+    }
+
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        fs::write(dir.join(PATCH_FILE), self.content())
+    }
+
+    fn patch_line(&self) -> usize {
+        self.lines
+            .iter()
+            .position(|x| x.trim_end() == "patch:")
+            .unwrap_or_default()
+    }
+
+    /// `parent` 下嵌套的行的范围及其缩进，没有嵌套的行时缩进为 None
+    fn children(&self, parent: usize) -> (usize, usize, Option<usize>) {
+        let indent = entry(&self.lines[parent]).map_or(0, |(indent, _, _)| indent);
+        let start = parent + 1;
+        let end = self.lines[start..]
+            .iter()
+            .position(|x| {
+                let content = x.trim_start();
+                !content.is_empty()
+                    && !content.starts_with('#')
+                    && x.len() - content.len() <= indent
+            })
+            .map(|x| start + x)
+            .unwrap_or(self.lines.len());
+        let child_indent = self.lines[start..end]
+            .iter()
+            .find_map(|x| entry(x))
+            .map(|(indent, _, _)| indent);
+        (start, end, child_indent)
+    }
+
+    /// 在 `parent` 下查找 `key` 所在的行，找不到时返回已存在的最深一层的父项所在的行及剩余的路径
+    fn search<'a>(&self, parent: usize, key: &'a str) -> Result<usize, (usize, &'a str)> {
+        let (start, end, indent) = self.children(parent);
+        let mut deepest = (parent, key);
+        for i in start..end {
+            let Some((line_indent, name, value)) = entry(&self.lines[i]) else {
+                continue;
+            };
+            if Some(line_indent) != indent {
+                continue;
+            }
+            if name == key {
+                return Ok(i);
+            }
+
+            let Some(rest) = key.strip_prefix(name).and_then(|x| x.strip_prefix('/')) else {
+                continue;
+            };
+            if is_nested(value) {
+                match self.search(i, rest) {
+                    Ok(i) => return Ok(i),
+                    Err(found) => deepest = found,
+                }
+            } else if value.starts_with('{') {
+                deepest = (i, rest);
+            }
+        }
+        Err(deepest)
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        match self.search(self.patch_line(), key) {
+            Ok(i) => {
+                let (_, _, value) = entry(&self.lines[i])?;
+                Some(value.trim_matches('"').to_string())
+            }
+            Err((i, rest)) => {
+                let (_, _, value) = entry(&self.lines[i])?;
+                let hash = flow_mapping(value)?;
+                let value = hash.get(&Yaml::String(rest.to_string()))?;
+                scalar(value).map(|x| x.trim_matches('"').to_string())
+            }
+        }
+    }
+
+    /// 将单行的 `key: {...}` 展开为嵌套的多行，无法展开时返回 false
+    fn expand(&mut self, i: usize) -> bool {
+        let Some((indent, name, value)) = entry(&self.lines[i]) else {
+            return false;
+        };
+        let Some(hash) = flow_mapping(value) else {
+            return false;
+        };
+
+        let pad = " ".repeat(indent);
+        let mut lines = vec![format!("{pad}{name}:")];
+        for (key, value) in &hash {
+            lines.push(format!(
+                "{pad}  {}: {}",
+                scalar(key).unwrap_or_default(),
+                scalar(value).unwrap_or_default()
+            ));
+        }
+        self.lines.splice(i..=i, lines);
+        true
+    }
+
+    /// 设置补丁中的一项，`value` 为 None 时删除该项
+    pub fn set(&mut self, key: &str, value: Option<&str>) {
+        let patch = self.patch_line();
+        let mut found = self.search(patch, key);
+        while let Err((i, _)) = found {
+            if i == patch || !self.expand(i) {
+                break;
+            }
+            found = self.search(patch, key);
+        }
+
+        match (found, value) {
+            (Ok(i), Some(value)) => {
+                let (indent, name, _) = entry(&self.lines[i]).unwrap();
+                self.lines[i] = format!("{}{name}: {value}", " ".repeat(indent));
+            }
+            (Ok(i), None) => self.remove(i),
+            (Err((parent, rest)), Some(value)) => {
+                // 无法展开的单行写法下不能再嵌套，在 patch 下使用完整的路径
+                let nested = entry(&self.lines[parent]).is_some_and(|(_, _, x)| is_nested(x));
+                if parent == patch || nested {
+                    self.insert(parent, rest, value)
+                } else {
+                    self.insert(patch, key, value)
+                }
+            }
+            (Err(_), None) => {}
+        }
+    }
+
+    /// 删除一项，其父项因此为空时一并删除
+    fn remove(&mut self, i: usize) {
+        let (indent, _, _) = entry(&self.lines[i]).unwrap();
+        self.lines.remove(i);
+
+        let patch = self.patch_line();
+        let parent = (0..i)
+            .rev()
+            .find(|&j| entry(&self.lines[j]).is_some_and(|(x, _, _)| x < indent));
+        if let Some(parent) = parent.filter(|&x| x > patch) {
+            if self.children(parent).2.is_none() {
+                self.remove(parent);
+            }
+        }
+    }
+
+    /// 在 `parent` 下添加一项，`parent` 为 patch 时使用 / 分隔的写法，否则使用嵌套的写法
+    fn insert(&mut self, parent: usize, key: &str, value: &str) {
+        let (_, end, indent) = self.children(parent);
+        let parent_indent = entry(&self.lines[parent]).map_or(0, |(x, _, _)| x);
+        let lines = if parent == self.patch_line() {
+            vec![format!("  {key}: {value}")]
+        } else {
+            let indent = indent.unwrap_or(parent_indent + 2);
+            let segments: Vec<&str> = key.split('/').collect();
+            segments
+                .iter()
+                .enumerate()
+                .map(|(depth, segment)| {
+                    let pad = " ".repeat(indent + depth * 2);
+                    if depth + 1 == segments.len() {
+                        format!("{pad}{segment}: {value}")
+                    } else {
+                        format!("{pad}{segment}:")
+                    }
+                })
+                .collect()
+        };
+
+        // 插入到最后一个非空行之后，缩进不超过 `parent` 的注释属于后面的内容
+        let mut i = end;
+        while i > parent + 1 {
+            let line = &self.lines[i - 1];
+            let content = line.trim_start();
+            let comment = content.starts_with('#') && line.len() - content.len() <= parent_indent;
+            if !content.is_empty() && !comment {
+                break;
+            }
+            i -= 1;
+        }
+        self.lines.splice(i..i, lines);
+    }
+}
+
+/// 补丁中的整句输入设置
+pub fn sentence(patch: &PatchFile) -> bool {
+    patch.get("grammar/language").is_some()
+}
+
+/// 补丁中的候选数量，未设置时为 None
+pub fn page_size(patch: &PatchFile) -> Option<u8> {
+    patch.get(PAGE_SIZE)?.parse().ok()
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+    match value {
+        "on" | "true" | "yes" => Some(true),
+        "off" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// 修改设备配置中的选项，选项或值无效时返回原因
+pub fn set_option(config: &mut IMUpdateConfig, name: &str, value: &str) -> Result<(), String> {
+    match name {
+        "sentence" => {
+            config.sentence =
+                parse_switch(value).ok_or(format!("sentence 的值只能是 on 或 off：{value}"))?;
+        }
+        "page_size" => {
+            config.page_size = match value {
+                "default" => None,
+                _ => match value.parse::<u8>() {
+                    Ok(size @ 1..=10) => Some(size),
+                    _ => return Err(format!("page_size 的值只能是 1 到 10 或 default：{value}")),
+                },
+            };
+        }
+        _ => {
+            let names: Vec<&str> = OPTIONS.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "不支持的选项 {name}，可以设置：{}",
+                names.join("、")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// 将设备配置中设置了的选项写入补丁，未开启整句输入或未设置候选数量时保留补丁中原有的值
+pub fn apply_options(patch: &mut PatchFile, config: &IMUpdateConfig) {
+    if config.sentence {
+        set_sentence(patch, true);
+    }
+    if let Some(page_size) = config.page_size {
+        patch.set(PAGE_SIZE, Some(&page_size.to_string()));
+    }
+}
+
+/// 按设备配置写入补丁中的某个选项，关闭整句输入或恢复默认候选数量时删除相关设置
+pub fn write_option(patch: &mut PatchFile, config: &IMUpdateConfig, name: &str) {
+    match name {
+        "sentence" => set_sentence(patch, config.sentence),
+        "page_size" => patch.set(
+            PAGE_SIZE,
+            config.page_size.map(|x| x.to_string()).as_deref(),
+        ),
+        _ => {}
+    }
+}
+
+/// 开启整句输入时使用 octagram 语言模型，关闭时删除相关设置
+fn set_sentence(patch: &mut PatchFile, enabled: bool) {
+    let values = [
+        ("grammar/language", GRAMMAR),
+        ("translator/contextual_suggestions", "true"),
+        ("translator/max_homophones", "7"),
+        ("translator/max_homographs", "7"),
+    ];
+    for (key, value) in values {
+        patch.set(key, enabled.then_some(value));
+    }
+}
+
+/// 整句输入所需的语言模型文件是否已安装
+pub fn grammar_installed(dir: &Path) -> bool {
+    dir.join(format!("{GRAMMAR}.gram")).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(sentence: bool, page_size: Option<u8>) -> IMUpdateConfig {
+        let mut config: IMUpdateConfig = toml::from_str(
+            r#"
+            name = "Squirrel"
+            user_dir = "/tmp/Rime"
+            update_dir = "/tmp/Squirrel"
+            max_backups = 1
+            sentence = false
+            version = "20240412"
+            "#,
+        )
+        .unwrap();
+        config.sentence = sentence;
+        config.page_size = page_size;
+        config
+    }
+
+    fn set(content: &str, key: &str, value: Option<&str>) -> String {
+        let mut patch = PatchFile::parse(content);
+        patch.set(key, value);
+        patch.content()
+    }
+
+    #[test]
+    fn set_in_empty_file() {
+        assert_eq!(
+            set("", PAGE_SIZE, Some("7")),
+            "patch:\n  menu/page_size: 7\n"
+        );
+        assert_eq!(set("", PAGE_SIZE, None), "patch:\n");
+    }
+
+    #[test]
+    fn get_flat_nested_and_flow_keys() {
+        let patch = PatchFile::parse(
+            "patch:\n  menu/page_size: 5 # 候选数量\n  grammar:\n    language: \"zh-hans\"\n  style: {horizontal: true}\n",
+        );
+        assert_eq!(patch.get(PAGE_SIZE).as_deref(), Some("5"));
+        assert_eq!(patch.get("grammar/language").as_deref(), Some("zh-hans"));
+        assert_eq!(patch.get("style/horizontal").as_deref(), Some("true"));
+        assert_eq!(patch.get("style/font_point"), None);
+        assert_eq!(page_size(&patch), Some(5));
+        assert!(sentence(&patch));
+    }
+
+    #[test]
+    fn set_existing_keys_in_place() {
+        assert_eq!(
+            set("patch:\n  menu/page_size: 5\n", PAGE_SIZE, Some("7")),
+            "patch:\n  menu/page_size: 7\n"
+        );
+        assert_eq!(
+            set("patch:\n  menu:\n    page_size: 5\n", PAGE_SIZE, Some("7")),
+            "patch:\n  menu:\n    page_size: 7\n"
+        );
+    }
+
+    #[test]
+    fn set_nested_key_under_existing_parent() {
+        assert_eq!(
+            set(
+                "patch:\n  translator:\n    enable_user_dict: false\n  menu/page_size: 5\n",
+                "translator/max_homophones",
+                Some("7")
+            ),
+            "patch:\n  translator:\n    enable_user_dict: false\n    max_homophones: 7\n  menu/page_size: 5\n"
+        );
+        assert_eq!(
+            set(
+                "patch:\n  translator:\n    enable_user_dict: false\n",
+                "translator/preset/max_homophones",
+                Some("7")
+            ),
+            "patch:\n  translator:\n    enable_user_dict: false\n    preset:\n      max_homophones: 7\n"
+        );
+    }
+
+    #[test]
+    fn expand_flow_mapping() {
+        assert_eq!(
+            set(
+                "patch:\n  menu: {page_size: 5, alternative_select_keys: abc}\n",
+                PAGE_SIZE,
+                Some("7")
+            ),
+            "patch:\n  menu:\n    page_size: 7\n    alternative_select_keys: abc\n"
+        );
+        // 值不都是标量时不展开，改为在 patch 下添加
+        assert_eq!(
+            set("patch:\n  menu: {keys: [a, b]}\n", PAGE_SIZE, Some("7")),
+            "patch:\n  menu: {keys: [a, b]}\n  menu/page_size: 7\n"
+        );
+    }
+
+    #[test]
+    fn remove_last_child_removes_parent() {
+        assert_eq!(
+            set(
+                "patch:\n  menu:\n    page_size: 5\n  style/horizontal: true\n",
+                PAGE_SIZE,
+                None
+            ),
+            "patch:\n  style/horizontal: true\n"
+        );
+        assert_eq!(
+            set(
+                "patch:\n  menu:\n    page_size: 5\n    alternative_select_keys: abc\n",
+                PAGE_SIZE,
+                None
+            ),
+            "patch:\n  menu:\n    alternative_select_keys: abc\n"
+        );
+    }
+
+    #[test]
+    fn keep_comments_and_unrelated_keys() {
+        let content = "# 个人设置\npatch:\n  # 横排显示\n  style/horizontal: true\n\n  menu/page_size: 5\n\n# 结束\n";
+        let mut patch = PatchFile::parse(content);
+        patch.set(PAGE_SIZE, Some("7"));
+        patch.set("grammar/language", Some(GRAMMAR));
+        assert_eq!(
+            patch.content(),
+            "# 个人设置\npatch:\n  # 横排显示\n  style/horizontal: true\n\n  menu/page_size: 7\n  grammar/language: zh-hans-t-essay-bgw\n\n# 结束\n"
+        );
+
+        patch.set(PAGE_SIZE, None);
+        patch.set("grammar/language", None);
+        assert_eq!(
+            patch.content(),
+            "# 个人设置\npatch:\n  # 横排显示\n  style/horizontal: true\n\n\n# 结束\n"
+        );
+    }
+
+    #[test]
+    fn apply_options_keeps_unset_options() {
+        let content = "patch:\n  menu/page_size: 9\n  grammar/language: zh-hans-t-essay-bgw\n";
+        let mut patch = PatchFile::parse(content);
+        apply_options(&mut patch, &config(false, None));
+        assert_eq!(patch.content(), content);
+
+        apply_options(&mut patch, &config(true, Some(6)));
+        assert_eq!(page_size(&patch), Some(6));
+        assert!(sentence(&patch));
+        assert_eq!(patch.get("translator/max_homophones").as_deref(), Some("7"));
+
+        write_option(&mut patch, &config(false, None), "sentence");
+        write_option(&mut patch, &config(false, None), "page_size");
+        assert_eq!(patch.content(), "patch:\n");
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use console::style;
+use indicatif::ProgressBar;
+use tempfile::tempdir;
+use zip::ZipArchive;
+
+use crate::{
+    backup::{BackupReason, BackupStore, Snapshot},
+    im::{select_assets, IMUpdateConfig},
+    release::Release,
+    utils::{cache_dir, download_file, get_bar_style, list_files, offline},
+};
+
+/// 压缩包中所有文件的相对路径，与解压后的路径一致
+pub fn zip_entries(path: &Path) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut files = BTreeSet::new();
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+
+        if let Some(name) = file.enclosed_name() {
+            files.insert(name.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(files)
+}
+
+/// 获取发布文件用于分析，缓存中是同一版本的文件时直接使用，否则下载到 `temp_dir` 中，不影响缓存
+pub async fn fetch_asset(
+    release: &Release,
+    name: &str,
+    url: &str,
+    temp_dir: &Path,
+) -> Option<PathBuf> {
+    let cached_version = fs::read_to_string(cache_dir().join("version.info")).unwrap_or_default();
+    let cached = cache_dir().join(name);
+    if cached_version == release.version && cached.exists() {
+        return Some(cached);
+    }
+
+    if offline() {
+        return None;
+    }
+
+    let file_path = temp_dir.join(name);
+    let pb = ProgressBar::new(100);
+    pb.set_prefix(format!("下载 {name}"));
+    pb.set_style(get_bar_style());
+    let result = download_file(url.to_string(), &file_path, |len, total| {
+        pb.set_length(total);
+        pb.inc(len as u64);
+    })
+    .await;
+    pb.finish_and_clear();
+
+    match result {
+        Ok(_) => Some(file_path),
+        Err(error) => {
+            println!("下载文件 {name} 失败: {error}");
+            None
+        }
+    }
+}
+
+fn print_backup_plan(config: &IMUpdateConfig) {
+    if config.max_backups == 0 {
+        println!("  未开启备份");
+        return;
+    }
+
+    let store = match BackupStore::open_read_only(config) {
+        Ok(store) => store,
+        Err(error) => {
+            println!("  打开备份目录失败：{error}");
+            return;
+        }
+    };
+
+    let source = if config.name == "Hamster" {
+        "设备上的 Rime 目录".to_string()
+    } else {
+        config.user_dir.display().to_string()
+    };
+    println!(
+        "  备份 {source} 到 {}，版本 {}",
+        config.backup_dir().display(),
+        config.version
+    );
+    let pending = store.new_snapshot(&config.version, BackupReason::Update, None);
+    for snapshot in store.to_prune(config, Some(pending)) {
+        println!(
+            "  {} 删除旧备份 {}",
+            style("-").red(),
+            snapshot.description()
+        );
+    }
+}
+
+fn print_files(tic: &str, files: &BTreeSet<String>) {
+    for file in files {
+        let tic = match tic {
+            "+" => style(tic).green(),
+            "~" => style(tic).yellow(),
+            _ => style(tic).red(),
+        };
+        println!("    {tic} {file}");
+    }
+}
+
+fn deploy_action(config: &IMUpdateConfig) -> String {
+    let exe = config
+        .exe
+        .as_ref()
+        .map(|exe| exe.display().to_string())
+        .unwrap_or_default();
+    match config.name.as_str() {
+        "Squirrel" => format!("执行 {exe} --reload"),
+        "Fcitx5" => "通过 fcitx5-curl 请求小企鹅重新部署".to_string(),
+        "Weasel" => "执行 WeaselDeployer.exe，更新期间会暂停小狼毫算法服务".to_string(),
+        "Hamster" => "需要在手机上手动重新部署".to_string(),
+        _ => "无".to_string(),
+    }
+}
+
+/// 显示发布中的哪些文件将被安装及原因
+pub fn print_asset_plan(release: &Release, config: &IMUpdateConfig) {
+    println!("版本 {} 中的文件：", style(&release.version).cyan());
+    for selection in select_assets(release, config) {
+        let (tic, name) = if selection.selected {
+            ("+", style(selection.asset.name).green())
+        } else {
+            ("-", style(selection.asset.name).dim())
+        };
+        println!("  {tic} {name:<40} {}", selection.reason);
+    }
+}
+
+/// 显示更新将要进行的操作，不修改任何内容
+pub async fn print_update_plan(name: &str, config: &IMUpdateConfig, release: &Release) {
+    println!(
+        "{}",
+        style(format!("设备 {name} ({}) 的更新计划", config.name)).bold()
+    );
+    println!(
+        "版本：{} -> {}（{} 通道）",
+        config.version,
+        style(&release.version).cyan(),
+        config.channel
+    );
+
+    print_asset_plan(release, config);
+
+    println!("备份：");
+    print_backup_plan(config);
+
+    let existing = if config.name == "Hamster" {
+        println!("上传到设备的文件（设备上的同名文件将被覆盖）：");
+        BTreeSet::new()
+    } else {
+        println!("用户目录 {} 中的变化：", config.user_dir.display());
+        list_files(&config.user_dir)
+    };
+
+    let temp_dir = tempdir().unwrap();
+    let mut created = BTreeSet::new();
+    let mut overwritten = BTreeSet::new();
+    for selection in select_assets(release, config).iter().filter(|x| x.selected) {
+        let asset = &selection.asset;
+        let Some(file_path) =
+            fetch_asset(release, &asset.name, &asset.download_url, temp_dir.path()).await
+        else {
+            println!("  无法获取 {}，不能列出其中的文件", asset.name);
+            continue;
+        };
+
+        match zip_entries(&file_path) {
+            Ok(entries) => {
+                for entry in entries {
+                    if existing.contains(&entry) {
+                        overwritten.insert(entry);
+                    } else {
+                        created.insert(entry);
+                    }
+                }
+            }
+            Err(error) => println!("  读取 {} 失败：{error}", asset.name),
+        }
+    }
+
+    if config.name == "Hamster" {
+        println!("  上传 {} 个文件", created.len());
+        print_files("~", &created);
+    } else {
+        println!(
+            "  新增 {} 个文件，覆盖 {} 个文件",
+            created.len(),
+            overwritten.len()
+        );
+        print_files("+", &created);
+        print_files("~", &overwritten);
+    }
+
+    println!("部署：{}", deploy_action(config));
+}
+
+/// 显示还原将要进行的操作，不修改任何内容，`partial` 为 true 时只还原备份中的文件
+pub fn print_restore_plan(name: &str, config: &IMUpdateConfig, snapshot: &Snapshot, partial: bool) {
+    println!(
+        "{}",
+        style(format!("设备 {name} ({}) 的还原计划", config.name)).bold()
+    );
+    println!(
+        "备份：{}（{}，{} 个文件）",
+        snapshot.description(),
+        snapshot.time(),
+        snapshot.files.len()
+    );
+    let restored: BTreeSet<String> = snapshot.files.iter().map(|x| x.path.clone()).collect();
+
+    if config.name == "Hamster" {
+        println!("上传到设备的文件（设备上的同名文件将被覆盖，其它文件保持不变）：");
+        print_files("~", &restored);
+    } else {
+        println!("用户目录 {} 中的变化：", config.user_dir.display());
+        let mut existing = list_files(&config.user_dir);
+        if let Ok(store) = BackupStore::open_read_only(config) {
+            existing.retain(|x| !store.is_excluded(x));
+        }
+        // 只还原部分文件时不删除其它文件
+        if partial {
+            existing.retain(|x| restored.contains(x));
+        }
+        let created: BTreeSet<String> = restored.difference(&existing).cloned().collect();
+        let overwritten: BTreeSet<String> = restored.intersection(&existing).cloned().collect();
+        let deleted: BTreeSet<String> = existing.difference(&restored).cloned().collect();
+        println!(
+            "  新增 {} 个文件，覆盖 {} 个文件，删除 {} 个文件",
+            created.len(),
+            overwritten.len(),
+            deleted.len()
+        );
+        print_files("+", &created);
+        print_files("~", &overwritten);
+        print_files("-", &deleted);
+    }
+
+    println!("部署：{}", deploy_action(config));
+}
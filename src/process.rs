@@ -0,0 +1,53 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+
+/// 正在运行的进程
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub pid: u32,
+    /// 可执行文件的完整路径，没有权限读取时为 None
+    pub exe: Option<PathBuf>,
+}
+
+/// 进程名与 `name` 相同，Windows 上不区分大小写
+fn name_matches(candidate: &OsStr, name: &str) -> bool {
+    let candidate = candidate.to_string_lossy();
+    if cfg!(windows) {
+        candidate.eq_ignore_ascii_case(name)
+    } else {
+        candidate == name
+    }
+}
+
+/// 查找进程名或可执行文件名为 `name` 的进程，如 Squirrel、WeaselServer.exe，有多个时返回 pid 最小的
+///
+/// 直接读取系统的进程信息（Linux 上为 /proc，macOS 上为 libproc，Windows 上为进程快照），
+/// 只按名称精确匹配，不会匹配到命令行中恰好包含该名称的其它进程
+pub fn find_process(name: &str) -> Option<Process> {
+    let mut system = System::new();
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        ProcessRefreshKind::nothing().with_exe(UpdateKind::OnlyIfNotSet),
+    );
+
+    system
+        .processes()
+        .values()
+        .filter(|process| {
+            name_matches(process.name(), name)
+                || process
+                    .exe()
+                    .and_then(Path::file_name)
+                    .is_some_and(|x| name_matches(x, name))
+        })
+        .map(|process| Process {
+            pid: process.pid().as_u32(),
+            exe: process.exe().map(Path::to_path_buf),
+        })
+        .min_by_key(|process| process.pid)
+}
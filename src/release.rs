@@ -1,7 +1,23 @@
-use reqwest::Method;
-use serde::Deserialize;
+use reqwest::{
+    header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Method, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-#[derive(Deserialize, Debug, Clone)]
+use crate::{
+    settings::Settings,
+    utils::{cache_dir, glob_match, http_client, read_timeout},
+};
+
+/// 缓存目录下保存发布信息的子目录，更新时清理缓存不会删除该目录
+pub const RELEASE_CACHE_DIR: &str = "releases";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Asset {
     pub name: String,
 
@@ -9,7 +25,7 @@ pub struct Asset {
     pub download_url: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Release {
     #[serde(rename = "tag_name")]
     pub version: String,
@@ -17,26 +33,440 @@ pub struct Release {
     #[serde(rename = "body")]
     pub intro: String,
     pub assets: Vec<Asset>,
+
+    /// 是否为预发布版本
+    #[serde(default)]
+    pub prerelease: bool,
+
+    /// 是否为草稿
+    #[serde(default)]
+    pub draft: bool,
+}
+
+/// 发布通道
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum Channel {
+    /// 正式版本
+    #[default]
+    Stable,
+    /// 包含预发布版本在内的最新版本
+    Prerelease,
+    /// 标签名称符合通配符（如 2024*-beta）的最新版本
+    Pattern(String),
+}
+
+impl Channel {
+    pub fn is_stable(&self) -> bool {
+        self == &Channel::Stable
+    }
+
+    fn accepts(&self, release: &Release) -> bool {
+        match self {
+            Channel::Stable => !release.prerelease,
+            Channel::Prerelease => true,
+            Channel::Pattern(pattern) => glob_match(pattern, &release.version),
+        }
+    }
+}
+
+impl From<String> for Channel {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "" | "stable" => Channel::Stable,
+            "prerelease" => Channel::Prerelease,
+            _ => Channel::Pattern(value),
+        }
+    }
+}
+
+impl From<Channel> for String {
+    fn from(value: Channel) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Channel::Stable => write!(f, "stable"),
+            Channel::Prerelease => write!(f, "prerelease"),
+            Channel::Pattern(pattern) => write!(f, "{pattern}"),
+        }
+    }
+}
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+const DEFAULT_REPO: &str = "sbsrf/home";
+
+/// 发布信息的来源，支持 GitHub 及兼容其接口的服务（如 Gitea），未设置的项使用默认值
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct ReleaseSource {
+    /// 接口地址，如 Gitea 的 https://gitea.example.com/api/v1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+    /// 仓库，格式为 owner/repo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+}
+
+impl ReleaseSource {
+    /// 以当前设置为准，未设置的项使用 `fallback` 中的值
+    pub fn or(&self, fallback: &ReleaseSource) -> ReleaseSource {
+        ReleaseSource {
+            api_url: self.api_url.clone().or(fallback.api_url.clone()),
+            repo: self.repo.clone().or(fallback.repo.clone()),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        self == &ReleaseSource::default()
+    }
+
+    pub fn api_url(&self) -> &str {
+        self.api_url
+            .as_deref()
+            .unwrap_or(GITHUB_API_URL)
+            .trim_end_matches('/')
+    }
+
+    pub fn repo(&self) -> &str {
+        self.repo
+            .as_deref()
+            .unwrap_or(DEFAULT_REPO)
+            .trim_matches('/')
+    }
+
+    pub fn is_github(&self) -> bool {
+        self.api_url() == GITHUB_API_URL
+    }
+
+    /// 访问来源使用的令牌，令牌只发送给 GitHub，避免泄露给其它服务
+    pub fn token(&self, settings: &Settings) -> Option<String> {
+        if self.is_github() {
+            settings.github.token()
+        } else {
+            None
+        }
+    }
+
+    /// 请求仓库信息，检查来源是否可以访问
+    pub async fn check(&self, settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+        let url = format!("{}/repos/{}", self.api_url(), self.repo());
+        let mut request = http_client()
+            .request(Method::GET, url)
+            .header("Accept", "application/vnd.github+json")
+            .timeout(read_timeout());
+        if let Some(token) = self.token(settings) {
+            request = request.bearer_auth(token);
+        }
+
+        let status = request.send().await?.status();
+        match status {
+            _ if status.is_success() => Ok(()),
+            StatusCode::NOT_FOUND => {
+                Err(format!("仓库 {} 不存在或没有访问权限", self.repo()).into())
+            }
+            StatusCode::UNAUTHORIZED => Err("令牌无效或已过期".into()),
+            _ => Err(format!("请求仓库信息失败: {status}").into()),
+        }
+    }
+
+    fn releases_url(&self) -> String {
+        format!("{}/repos/{}/releases", self.api_url(), self.repo())
+    }
+
+    /// 缓存文件名，不同来源的发布信息分开缓存
+    fn cache_key(&self) -> String {
+        let host = self
+            .api_url()
+            .split("://")
+            .last()
+            .unwrap_or_default()
+            .to_string();
+        sanitize(&format!("{host}/{}", self.repo()))
+    }
+}
+
+/// 将任意字符串转换为可以用作文件名的形式
+pub fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl fmt::Display for ReleaseSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_github() {
+            write!(f, "{}", self.repo())
+        } else {
+            write!(f, "{} ({})", self.repo(), self.api_url())
+        }
+    }
+}
+
+/// 缓存的发布信息，用于条件请求及离线使用
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CachedRelease {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub release: Release,
+}
+
+impl CachedRelease {
+    fn path(source: &ReleaseSource, channel: &Channel) -> PathBuf {
+        let name = match channel {
+            Channel::Stable => source.cache_key(),
+            // 预发布及通配符通道使用发布列表，与正式版本分开缓存
+            _ => format!("{}_{}", source.cache_key(), sanitize(&channel.to_string())),
+        };
+        cache_dir()
+            .join(RELEASE_CACHE_DIR)
+            .join(format!("{name}.json"))
+    }
+
+    fn read(source: &ReleaseSource, channel: &Channel) -> Option<Self> {
+        let content = fs::read_to_string(Self::path(source, channel)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn write(
+        &self,
+        source: &ReleaseSource,
+        channel: &Channel,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Self::path(source, channel);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
 }
 
 impl Release {
-    pub async fn init() -> Result<Self, reqwest::Error> {
-        GithubRelease::init().await
+    /// 从 `source` 获取 `channel` 通道中最新的发布信息，`offline` 为 true 时只使用缓存
+    pub async fn init(
+        settings: &Settings,
+        source: &ReleaseSource,
+        channel: &Channel,
+        offline: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let cached = CachedRelease::read(source, channel);
+        if offline {
+            return match cached {
+                Some(cached) => Ok(cached.release),
+                None => Err("没有缓存的发布信息，无法在离线模式下使用".into()),
+            };
+        }
+
+        let token = source.token(settings);
+        match GithubRelease::init(source, channel, token.as_deref(), cached.as_ref()).await {
+            Ok(fetched) => {
+                if let Err(error) = fetched.write(source, channel) {
+                    eprintln!("缓存发布信息失败: {error}");
+                }
+                Ok(fetched.release)
+            }
+            Err(error) => match cached {
+                Some(cached) if error.is::<RateLimitError>() || error.is::<reqwest::Error>() => {
+                    eprintln!("{error}");
+                    println!("使用上次获取的发布信息: {}", cached.release.version);
+                    Ok(cached.release)
+                }
+                _ => Err(error),
+            },
+        }
+    }
+}
+
+/// GitHub API 请求次数超出限制
+#[derive(Debug)]
+pub struct RateLimitError {
+    /// 限制解除的时间（Unix 时间戳，秒）
+    reset_at: Option<u64>,
+    authenticated: bool,
+}
+
+impl RateLimitError {
+    fn from_headers(headers: &HeaderMap, authenticated: bool) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let reset_at = header("x-ratelimit-reset").or(header("retry-after").map(|secs| now + secs));
+
+        Self {
+            reset_at,
+            authenticated,
+        }
+    }
+}
+
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GitHub API 请求次数已达上限")?;
+        if let Some(reset_at) = self.reset_at {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let minutes = reset_at.saturating_sub(now).div_ceil(60);
+            write!(f, "，将在 {minutes} 分钟后重置")?;
+        }
+        if !self.authenticated {
+            write!(
+                f,
+                "。可以通过 GITHUB_TOKEN 环境变量或设置文件中的 github.token 使用令牌访问以提高限额"
+            )?;
+        }
+        Ok(())
     }
 }
+
+impl std::error::Error for RateLimitError {}
+
+/// GitHub API 的请求额度
+#[derive(Debug)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    /// 额度重置的时间（Unix 时间戳，秒）
+    pub reset_at: u64,
+    pub authenticated: bool,
+}
+
+impl RateLimit {
+    /// 查询当前的请求额度，查询本身不消耗额度
+    pub async fn fetch(settings: &Settings) -> Result<Self, Box<dyn std::error::Error>> {
+        let token = settings.github.token();
+        let mut request = http_client()
+            .request(Method::GET, format!("{GITHUB_API_URL}/rate_limit"))
+            .header("Accept", "application/vnd.github+json")
+            .timeout(read_timeout());
+        if let Some(token) = &token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?.error_for_status()?;
+        let body = response.json::<serde_json::Value>().await?;
+        let core = &body["resources"]["core"];
+        Ok(Self {
+            limit: core["limit"].as_u64().unwrap_or_default(),
+            remaining: core["remaining"].as_u64().unwrap_or_default(),
+            reset_at: core["reset"].as_u64().unwrap_or_default(),
+            authenticated: token.is_some(),
+        })
+    }
+
+    /// 距离额度重置的分钟数
+    pub fn minutes_to_reset(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.reset_at.saturating_sub(now).div_ceil(60)
+    }
+}
+
 pub struct GithubRelease {}
 
 impl GithubRelease {
-    pub async fn init() -> Result<Release, reqwest::Error> {
-        let response = reqwest::Client::new()
-            .request(
-                Method::GET,
-                "https://api.github.com/repos/sbsrf/home/releases/latest",
-            )
-            .header("User-Agent", "Sbsrf-Update-App")
+    /// 请求最新的发布信息，有缓存时发送条件请求，未发生变化时直接使用缓存
+    pub async fn init(
+        source: &ReleaseSource,
+        channel: &Channel,
+        token: Option<&str>,
+        cached: Option<&CachedRelease>,
+    ) -> Result<CachedRelease, Box<dyn std::error::Error>> {
+        // latest 接口不包含预发布版本，其它通道需要从发布列表中查找
+        let url = match channel {
+            Channel::Stable => format!("{}/latest", source.releases_url()),
+            _ => format!("{}?per_page=50", source.releases_url()),
+        };
+        let mut request = http_client()
+            .request(Method::GET, url)
             .header("Accept", "application/vnd.github+json")
-            .send()
-            .await?;
-        response.json::<Release>().await
+            .timeout(read_timeout());
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(cached) = cached {
+            if let Some(etag) = &cached.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            if let Some(cached) = cached {
+                return Ok(cached.clone());
+            }
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS
+            || (status == StatusCode::FORBIDDEN
+                && response
+                    .headers()
+                    .get("x-ratelimit-remaining")
+                    .is_some_and(|remaining| remaining == "0"))
+        {
+            return Err(Box::new(RateLimitError::from_headers(
+                response.headers(),
+                token.is_some(),
+            )));
+        }
+
+        if !status.is_success() {
+            let message = response
+                .json::<serde_json::Value>()
+                .await
+                .ok()
+                .and_then(|body| body["message"].as_str().map(str::to_string))
+                .unwrap_or_default();
+            return Err(format!("获取 {source} 的发布信息失败: {status} {message}").into());
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+
+        let release = match channel {
+            Channel::Stable => response.json::<Release>().await?,
+            _ => response
+                .json::<Vec<Release>>()
+                .await?
+                .into_iter()
+                .find(|release| !release.draft && channel.accepts(release))
+                .ok_or_else(|| format!("{source} 中没有 {channel} 通道的发布版本"))?,
+        };
+
+        Ok(CachedRelease {
+            etag,
+            last_modified,
+            release,
+        })
     }
 }
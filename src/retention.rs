@@ -0,0 +1,373 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use time::{Duration, OffsetDateTime};
+
+use crate::backup::Snapshot;
+
+/// 备份的保留策略，与设备配置中的 max_backups 一起决定清理哪些备份
+///
+/// 自动备份只要满足任意一条保留规则就会被保留，之后再按时间及总大小删除；
+/// 手动备份、导入的备份及固定的备份不会被清理。未设置的项不生效
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Retention {
+    /// 保留最近 N 天中每天最新的备份
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_daily: Option<u32>,
+    /// 保留最近 N 周中每周最新的备份
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_weekly: Option<u32>,
+    /// 保留最近 N 个月中每月最新的备份
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_monthly: Option<u32>,
+    /// 删除超过该天数的备份
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u32>,
+    /// 所有备份去重后的总大小上限，单位 MB，超过时从最旧的备份开始删除
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_total_size: Option<u64>,
+}
+
+impl Retention {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// 按保留策略需要删除的备份，`snapshots` 按备份时间排序，返回的结果同样由旧到新
+pub fn select_prune(
+    snapshots: &[Snapshot],
+    keep_last: u32,
+    retention: &Retention,
+    now: OffsetDateTime,
+) -> Vec<Snapshot> {
+    let has_keep_rule = keep_last > 0
+        || retention.keep_daily.is_some()
+        || retention.keep_weekly.is_some()
+        || retention.keep_monthly.is_some();
+    if !has_keep_rule && retention.max_age_days.is_none() && retention.max_total_size.is_none() {
+        return vec![];
+    }
+
+    let (protected, candidates): (Vec<&Snapshot>, Vec<&Snapshot>) =
+        snapshots.iter().partition(|x| x.is_protected());
+    // 由新到旧
+    let candidates: Vec<&Snapshot> = candidates.into_iter().rev().collect();
+
+    // 没有保留规则时全部保留，只按时间及总大小删除
+    let mut keep = vec![!has_keep_rule; candidates.len()];
+    for flag in keep.iter_mut().take(keep_last as usize) {
+        *flag = true;
+    }
+    keep_buckets(&candidates, &mut keep, retention.keep_daily, |time| {
+        (time.year(), time.ordinal() as u32)
+    });
+    keep_buckets(&candidates, &mut keep, retention.keep_weekly, |time| {
+        let (year, week, _) = time.to_iso_week_date();
+        (year, week as u32)
+    });
+    keep_buckets(&candidates, &mut keep, retention.keep_monthly, |time| {
+        (time.year(), time.month() as u32)
+    });
+
+    if let Some(days) = retention.max_age_days {
+        let limit = now - Duration::days(days as i64);
+        for (i, snapshot) in candidates.iter().enumerate() {
+            if snapshot.created() < limit {
+                keep[i] = false;
+            }
+        }
+    }
+
+    if let Some(max_size) = retention.max_total_size {
+        let limit = max_size * 1024 * 1024;
+        loop {
+            let kept = protected.iter().copied().chain(
+                candidates
+                    .iter()
+                    .zip(&keep)
+                    .filter(|(_, keep)| **keep)
+                    .map(|(x, _)| *x),
+            );
+            if total_size(kept) <= limit {
+                break;
+            }
+
+            match keep.iter().rposition(|x| *x) {
+                Some(oldest) => keep[oldest] = false,
+                None => break,
+            }
+        }
+    }
+
+    candidates
+        .iter()
+        .zip(&keep)
+        .filter(|(_, keep)| !**keep)
+        .map(|(x, _)| (*x).clone())
+        .rev()
+        .collect()
+}
+
+/// 每个时间段只保留最新的一个备份，共保留 `count` 个时间段
+fn keep_buckets<F>(candidates: &[&Snapshot], keep: &mut [bool], count: Option<u32>, key: F)
+where
+    F: Fn(OffsetDateTime) -> (i32, u32),
+{
+    let Some(count) = count else {
+        return;
+    };
+
+    let mut seen = HashSet::new();
+    for (i, snapshot) in candidates.iter().enumerate() {
+        if seen.len() >= count as usize {
+            break;
+        }
+        if seen.insert(key(snapshot.created())) {
+            keep[i] = true;
+        }
+    }
+}
+
+/// 相同内容的文件只计算一次
+fn total_size<'a>(snapshots: impl Iterator<Item = &'a Snapshot>) -> u64 {
+    let mut seen = HashSet::new();
+    snapshots
+        .flat_map(|x| &x.files)
+        .filter(|x| seen.insert(&x.hash))
+        .map(|x| x.size)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use time::format_description::well_known::Rfc3339;
+
+    use super::*;
+    use crate::backup::{BackupReason, FileEntry};
+
+    const MB: u64 = 1024 * 1024;
+
+    fn snapshot(id: &str, created_at: &str, reason: BackupReason, files: &[&str]) -> Snapshot {
+        let files: Vec<FileEntry> = files
+            .iter()
+            .map(|hash| FileEntry {
+                path: format!("{hash}.txt"),
+                hash: hash.to_string(),
+                size: MB,
+            })
+            .collect();
+        Snapshot {
+            id: id.to_string(),
+            version: "20240412".to_string(),
+            created_at: created_at.to_string(),
+            device: "test".to_string(),
+            reason,
+            label: None,
+            size: files.iter().map(|x| x.size).sum(),
+            pinned: false,
+            files,
+        }
+    }
+
+    fn updates(times: &[&str]) -> Vec<Snapshot> {
+        times
+            .iter()
+            .map(|time| snapshot(time, time, BackupReason::Update, &[]))
+            .collect()
+    }
+
+    fn time(value: &str) -> OffsetDateTime {
+        OffsetDateTime::parse(value, &Rfc3339).unwrap()
+    }
+
+    fn pruned(snapshots: &[Snapshot], keep_last: u32, retention: &Retention) -> Vec<String> {
+        select_prune(
+            snapshots,
+            keep_last,
+            retention,
+            time("2024-04-12T12:00:00Z"),
+        )
+        .into_iter()
+        .map(|x| x.id)
+        .collect()
+    }
+
+    #[test]
+    fn no_rules_keeps_everything() {
+        let snapshots = updates(&["2024-04-10T10:00:00Z", "2024-04-11T10:00:00Z"]);
+        assert!(pruned(&snapshots, 0, &Retention::default()).is_empty());
+    }
+
+    #[test]
+    fn keep_last() {
+        let snapshots = updates(&[
+            "2024-04-12T08:00:00Z",
+            "2024-04-12T09:00:00Z",
+            "2024-04-12T10:00:00Z",
+            "2024-04-12T11:00:00Z",
+        ]);
+        assert_eq!(
+            pruned(&snapshots, 2, &Retention::default()),
+            ["2024-04-12T08:00:00Z", "2024-04-12T09:00:00Z"]
+        );
+    }
+
+    #[test]
+    fn keep_daily() {
+        let snapshots = updates(&[
+            "2024-04-09T12:00:00Z",
+            "2024-04-10T10:00:00Z",
+            "2024-04-10T11:00:00Z",
+            "2024-04-11T10:00:00Z",
+            "2024-04-11T11:00:00Z",
+            "2024-04-12T10:00:00Z",
+        ]);
+        let retention = Retention {
+            keep_daily: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            pruned(&snapshots, 0, &retention),
+            [
+                "2024-04-09T12:00:00Z",
+                "2024-04-10T10:00:00Z",
+                "2024-04-10T11:00:00Z",
+                "2024-04-11T10:00:00Z",
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_weekly() {
+        // 2024-04-08 是周一
+        let snapshots = updates(&[
+            "2024-03-26T10:00:00Z",
+            "2024-04-02T10:00:00Z",
+            "2024-04-05T10:00:00Z",
+            "2024-04-08T10:00:00Z",
+            "2024-04-12T10:00:00Z",
+        ]);
+        let retention = Retention {
+            keep_weekly: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            pruned(&snapshots, 0, &retention),
+            [
+                "2024-03-26T10:00:00Z",
+                "2024-04-02T10:00:00Z",
+                "2024-04-08T10:00:00Z",
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_monthly_with_keep_last() {
+        let snapshots = updates(&[
+            "2024-01-15T10:00:00Z",
+            "2024-02-10T10:00:00Z",
+            "2024-02-20T10:00:00Z",
+            "2024-03-05T10:00:00Z",
+            "2024-03-06T10:00:00Z",
+        ]);
+        let retention = Retention {
+            keep_monthly: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            pruned(&snapshots, 2, &retention),
+            ["2024-01-15T10:00:00Z", "2024-02-10T10:00:00Z"]
+        );
+    }
+
+    #[test]
+    fn max_age_overrides_keep_rules() {
+        let snapshots = updates(&[
+            "2024-04-01T10:00:00Z",
+            "2024-04-05T11:00:00Z",
+            "2024-04-06T10:00:00Z",
+            "2024-04-12T10:00:00Z",
+        ]);
+        let retention = Retention {
+            max_age_days: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(
+            pruned(&snapshots, 10, &retention),
+            ["2024-04-01T10:00:00Z", "2024-04-05T11:00:00Z"]
+        );
+    }
+
+    #[test]
+    fn max_total_size_counts_shared_files_once() {
+        let mut snapshots = vec![
+            snapshot(
+                "a",
+                "2024-04-09T10:00:00Z",
+                BackupReason::Update,
+                &["a", "s"],
+            ),
+            snapshot(
+                "b",
+                "2024-04-10T10:00:00Z",
+                BackupReason::Update,
+                &["b", "s"],
+            ),
+            snapshot(
+                "c",
+                "2024-04-11T10:00:00Z",
+                BackupReason::Update,
+                &["c", "s"],
+            ),
+        ];
+        let retention = Retention {
+            max_total_size: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(pruned(&snapshots, 0, &retention), ["a"]);
+
+        // 手动备份计入总大小，但不会被删除
+        snapshots.insert(
+            0,
+            snapshot("m", "2024-04-01T10:00:00Z", BackupReason::Manual, &["m"]),
+        );
+        assert_eq!(pruned(&snapshots, 0, &retention), ["a", "b"]);
+
+        let retention = Retention {
+            max_total_size: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(pruned(&snapshots, 0, &retention), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn protected_snapshots_are_kept() {
+        let now = OffsetDateTime::now_utc();
+        let recent = (now - Duration::days(2)).format(&Rfc3339).unwrap();
+        let mut pinned = snapshot("pinned", "2024-04-01T10:00:00Z", BackupReason::Update, &[]);
+        pinned.pinned = true;
+        let snapshots = vec![
+            snapshot("manual", "2024-04-01T10:00:00Z", BackupReason::Manual, &[]),
+            snapshot("import", "2024-04-02T10:00:00Z", BackupReason::Import, &[]),
+            pinned,
+            snapshot(
+                "old-restore",
+                "2024-04-03T10:00:00Z",
+                BackupReason::Restore,
+                &[],
+            ),
+            snapshot("update", "2024-04-04T10:00:00Z", BackupReason::Update, &[]),
+            snapshot("restore", &recent, BackupReason::Restore, &[]),
+        ];
+        let retention = Retention {
+            max_age_days: Some(1),
+            ..Default::default()
+        };
+        let pruned: Vec<String> = select_prune(&snapshots, 0, &retention, now)
+            .into_iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(pruned, ["old-restore", "update"]);
+    }
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf, sync::OnceLock};
+
+use crate::{release::ReleaseSource, utils::work_dir};
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// 全局设置，对所有设备生效
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Settings {
+    /// 网络设置
+    pub network: NetworkSettings,
+    /// GitHub 设置
+    pub github: GithubSettings,
+    /// 发布信息的来源，设备配置中的 source 优先
+    pub release: ReleaseSource,
+    /// 备份设置
+    pub backup: BackupSettings,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct BackupSettings {
+    /// 是否压缩备份的文件
+    pub compress: bool,
+    /// 不备份的文件，支持通配符，路径相对于 Rime 用户目录
+    pub exclude: Vec<String>,
+    /// 是否备份用户词库
+    pub user_dict: bool,
+}
+
+/// 用户词库相关的文件
+const USER_DICT_PATTERNS: [&str; 4] = ["*.userdb/**", "*.userdb.txt", "*.userdb.kct", "sync/**"];
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            compress: true,
+            // build 目录在部署时会重新生成，LOCK 及临时文件在输入法运行时被占用或随时变化
+            exclude: ["build/**", "*.userdb/LOCK", "*.userdb/LOG*", "**.tmp"]
+                .map(String::from)
+                .to_vec(),
+            user_dict: true,
+        }
+    }
+}
+
+impl BackupSettings {
+    /// 所有不备份的文件规则，不备份用户词库时包含词库相关的文件
+    pub fn exclude_patterns(&self) -> Vec<&str> {
+        let mut patterns: Vec<&str> = self.exclude.iter().map(String::as_str).collect();
+        if !self.user_dict {
+            patterns.extend(USER_DICT_PATTERNS);
+        }
+        patterns
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// 代理地址，如 http://127.0.0.1:7890，未设置时使用 HTTPS_PROXY 等环境变量
+    pub proxy: Option<String>,
+    /// 额外信任的 CA 证书（PEM 格式），用于需要经过企业代理的网络
+    pub ca_cert: Option<PathBuf>,
+    /// 连接超时时间，单位秒
+    pub connect_timeout: u64,
+    /// 读取超时时间，单位秒
+    pub read_timeout: u64,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            ca_cert: None,
+            connect_timeout: 10,
+            read_timeout: 30,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct GithubSettings {
+    /// 访问 GitHub API 的令牌，GITHUB_TOKEN 环境变量优先
+    pub token: Option<String>,
+}
+
+impl GithubSettings {
+    pub fn token(&self) -> Option<String> {
+        std::env::var("GITHUB_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())
+            .or(self.token.clone())
+    }
+}
+
+impl Settings {
+    pub fn path() -> PathBuf {
+        work_dir().join("settings.toml")
+    }
+
+    /// 读取设置文件，之后可以通过 `Settings::current` 获取
+    pub fn init() -> Result<&'static Self, Box<dyn std::error::Error>> {
+        let settings = Self::load()?;
+        Ok(SETTINGS.get_or_init(|| settings))
+    }
+
+    pub fn current() -> &'static Self {
+        SETTINGS.get_or_init(Self::default)
+    }
+
+    /// 读取并解析设置文件，文件不存在时使用默认设置
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        toml::from_str(&content)
+            .map_err(|error| format!("解析设置文件 {} 失败: {error}", path.display()).into())
+    }
+}
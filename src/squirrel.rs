@@ -1,7 +1,7 @@
 use std::{
-    fs::{self, File},
+    fs::File,
     io::{copy, Cursor},
-    path::{Path, PathBuf},
+    path::PathBuf,
     process::Command,
 };
 
@@ -11,11 +11,14 @@ use tempfile::tempdir;
 use zip::ZipArchive;
 
 use crate::{
-    im::{check_file_item, IMUpdateConfig, InputMethod},
-    release::Release,
+    backup::{backup_user_dir, restore_user_dir, BackupReason, Snapshot},
+    custom::apply_to_user_dir,
+    im::{select_assets, AssetRules, IMUpdateConfig, InputMethod},
+    process::find_process,
+    release::{Channel, Release, ReleaseSource},
+    retention::Retention,
     utils::{
-        copy_dir_contents, download_and_install, download_file, ensure_max_backups, get_bar_style,
-        get_spinner_style, grep, open, work_dir,
+        cache_dir, download_and_install, download_file, get_bar_style, home_dir, open, work_dir,
     },
 };
 
@@ -36,18 +39,25 @@ impl Squirrel {
             exe: Some(PathBuf::from(
                 "/Library/Input Methods/Squirrel.app/Contents/MacOS/Squirrel",
             )),
-            user_dir: PathBuf::from(std::env::var("HOME").unwrap()).join("Library/Rime"),
+            user_dir: home_dir().unwrap_or_default().join("Library/Rime"),
             update_dir,
             max_backups: 1,
             sentence: false,
+            page_size: None,
             version: "20051203".to_string(),
+            channel: Channel::default(),
+            assets: AssetRules::default(),
+            source: ReleaseSource::default(),
+            retention: Retention::default(),
+            sync: false,
+            custom: vec![],
         }
     }
 }
 
 impl InputMethod for Squirrel {
     async fn install(&self, name: &str, download_url: &str) {
-        let file_path = work_dir().join("_cache").join(name);
+        let file_path = cache_dir().join(name);
         let pb = ProgressBar::new(100);
         pb.set_prefix(format!("下载 {}", name));
         pb.set_style(get_bar_style());
@@ -90,67 +100,30 @@ impl InputMethod for Squirrel {
         }
     }
 
-    async fn backup(&self) {
-        let max_backups = self.config.max_backups;
-        if self.config.max_backups == 0 {
-            return;
-        }
-
-        let backup_path = self.config.update_dir.join("backups");
-        let target = backup_path.join(&self.config.version);
-        if target.exists() {
-            println!("当前版本已经备份在: {}，略过", target.display());
-            return;
-        }
-
-        ensure_max_backups(&backup_path, max_backups);
-
-        println!("备份当前版本到：{}", target.display());
-        let pb = ProgressBar::new_spinner();
-        pb.set_prefix(format!("备份当前版本 {}", &self.config.version));
-        pb.set_style(get_spinner_style());
-        let source = &self.config.user_dir;
-        if let Err(error) = copy_dir_contents(source, &target, |path| {
-            pb.set_message(format!("{}", path.display()));
-            pb.inc(1);
-        }) {
-            println!("备份当前版本失败：{error}");
-        }
-        pb.finish_with_message("完成");
+    async fn backup(&self, reason: BackupReason, label: Option<String>) -> Option<Snapshot> {
+        backup_user_dir(&self.config, reason, label)
     }
 
-    async fn restore(&self, version: &Path) {
-        let from = version;
-        let to = &self.config.user_dir;
-        fs::remove_dir_all(to).unwrap();
-
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(get_spinner_style());
-        pb.set_prefix("还原");
-        if let Err(error) = copy_dir_contents(from, to, |entry| {
-            pb.set_message(format!("{}", entry.display()));
-            pb.inc(1);
-        }) {
-            println!("还原失败：{error}")
-        }
-        pb.finish_with_message("完成");
+    async fn restore(&self, snapshot: &Snapshot, partial: bool) {
+        restore_user_dir(&self.config, snapshot, partial);
 
         println!("正在重新部署...");
         self.deploy();
     }
 
-    async fn update(&self, release: Release) {
+    async fn update(&self, release: Release) -> Result<Option<Snapshot>, String> {
         println!("开始为本地的鼠须管更新声笔输入法...");
-        self.backup().await;
+        let snapshot = self.backup(BackupReason::Update, None).await;
 
         let m = MultiProgress::new();
         let mut tasks = vec![];
 
-        for asset in release.assets {
-            if !check_file_item(&asset.name, "squirrel", self.config.sentence) {
+        for selection in select_assets(&release, &self.config) {
+            if !selection.selected {
                 continue;
             }
 
+            let asset = selection.asset;
             let name = asset.name;
             let download_url = asset.download_url;
             let target_dir = self.config.clone().user_dir;
@@ -169,8 +142,11 @@ impl InputMethod for Squirrel {
             }
         }
 
+        apply_to_user_dir(&self.config);
+
         println!("文件更新完成，重新部署...");
         self.deploy();
+        Ok(snapshot)
     }
 
     fn deploy(&self) {
@@ -180,21 +156,27 @@ impl InputMethod for Squirrel {
             }
         }
     }
+
+    fn sync(&self) {
+        if let Some(exe) = &self.config.exe {
+            if let Err(error) = Command::new(exe.as_os_str()).arg("--sync").output() {
+                println!("鼠须管同步用户数据失败: {error}")
+            }
+        }
+    }
 }
 
 pub fn get_squirrel() -> Result<Option<Squirrel>, Box<dyn std::error::Error>> {
-    if let Ok(exe_path) = grep("[S]quirrel") {
-        let update_dir = work_dir().join("Squirrel");
-        let config_file = update_dir.join("config.toml");
-        if config_file.exists() {
-            // 配置文件存在，直接读取
-            let toml = fs::read_to_string(config_file)?;
-            let config: IMUpdateConfig = toml::from_str(&toml)?;
+    if let Some(process) = find_process("Squirrel") {
+        if let Some(config) = IMUpdateConfig::new("Squirrel")? {
             return Ok(Some(Squirrel::new(config)));
         }
 
         let mut config = Squirrel::default_config();
-        config.exe = Some(PathBuf::from(exe_path));
+        // 没有权限读取可执行文件的路径时使用默认的安装位置
+        if let Some(exe) = process.exe {
+            config.exe = Some(exe);
+        }
         config.write_config();
 
         return Ok(Some(Squirrel::new(config)));
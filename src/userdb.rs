@@ -0,0 +1,385 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::im::IMUpdateConfig;
+
+/// 合并后的词库保存在同步目录的该子目录中，输入法同步用户数据时会将其导入
+pub const MERGE_DIR: &str = "sbsrf-update";
+
+/// 词库文本快照的扩展名
+const SNAPSHOT_EXTENSION: &str = ".userdb.txt";
+
+/// 用户词库中一个词条的使用记录
+#[derive(Debug, Clone, Copy, Default)]
+struct Entry {
+    /// 使用次数，为负数时表示词条已被删除
+    commits: i64,
+    /// 随时间衰减的权重
+    dee: f64,
+    /// 最后使用时词库的 tick
+    tick: u64,
+}
+
+impl Entry {
+    fn parse(value: &str) -> Self {
+        let mut entry = Self::default();
+        for field in value.split_whitespace() {
+            match field.split_once('=') {
+                Some(("c", x)) => entry.commits = x.parse().unwrap_or_default(),
+                Some(("d", x)) => entry.dee = x.parse().unwrap_or_default(),
+                Some(("t", x)) => entry.tick = x.parse().unwrap_or_default(),
+                _ => {}
+            }
+        }
+        entry
+    }
+
+    /// 将权重衰减到词库的 tick，与 librime 的算法一致
+    fn decayed(&self, tick: u64) -> f64 {
+        if self.tick < tick {
+            self.dee * ((self.tick as f64 - tick as f64) / 200.0).exp()
+        } else {
+            self.dee
+        }
+    }
+}
+
+/// Rime 用户词库的文本快照，即同步用户数据时生成的 *.userdb.txt
+#[derive(Debug, Clone)]
+pub struct UserDb {
+    /// 词库名称，如 sbsrf
+    pub name: String,
+    /// 文件头中的 #@/ 元数据，不含 db_name 及 tick
+    metadata: BTreeMap<String, String>,
+    tick: u64,
+    /// 编码与词条之间以制表符分隔
+    entries: BTreeMap<String, Entry>,
+}
+
+impl UserDb {
+    pub fn parse(content: &str) -> io::Result<Self> {
+        let mut name = None;
+        let mut metadata = BTreeMap::new();
+        let mut tick = 0;
+        let mut entries = BTreeMap::new();
+
+        for line in content.lines() {
+            if let Some(meta) = line.strip_prefix("#@/") {
+                let (key, value) = meta.split_once('\t').unwrap_or((meta, ""));
+                match key {
+                    // 旧版本的 librime 记录的名称带有 .userdb
+                    "db_name" => name = Some(value.trim_end_matches(".userdb").to_string()),
+                    "tick" => tick = value.parse().unwrap_or_default(),
+                    _ => {
+                        metadata.insert(key.to_string(), value.to_string());
+                    }
+                }
+                continue;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // 编码\t词条\tc=1 d=1 t=1
+            let Some((key, value)) = line.rsplit_once('\t') else {
+                continue;
+            };
+            entries.insert(key.to_string(), Entry::parse(value));
+        }
+
+        if metadata.get("db_type").map(String::as_str) != Some("userdb") {
+            return Err(io::Error::new(ErrorKind::InvalidData, "不是用户词库快照"));
+        }
+        let Some(name) = name else {
+            return Err(io::Error::new(ErrorKind::InvalidData, "缺少词库名称"));
+        };
+
+        Ok(Self {
+            name,
+            metadata,
+            tick,
+            entries,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 按 librime 同步用户数据时的方式合并：使用次数取绝对值较大者，权重衰减后取较大者，
+    /// 词条的 tick 及词库的 tick 都取较新的值
+    pub fn merge(&mut self, other: &UserDb) {
+        let tick = self.tick.max(other.tick);
+        for (key, theirs) in &other.entries {
+            let ours = self.entries.get(key).copied().unwrap_or_default();
+            let commits = if ours.commits.abs() < theirs.commits.abs() {
+                theirs.commits
+            } else {
+                ours.commits
+            };
+            let dee = ours.decayed(self.tick).max(theirs.decayed(other.tick));
+            self.entries
+                .insert(key.clone(), Entry { commits, dee, tick });
+        }
+        self.tick = tick;
+        for (key, value) in &other.metadata {
+            self.metadata
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+
+    /// 输出为 librime 可以导入的文本快照
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# Rime user dictionary\n");
+        writeln!(text, "#@/db_name\t{}", self.name).unwrap();
+        for (key, value) in &self.metadata {
+            writeln!(text, "#@/{key}\t{value}").unwrap();
+        }
+        writeln!(text, "#@/tick\t{}", self.tick).unwrap();
+        for (key, entry) in &self.entries {
+            writeln!(
+                text,
+                "{key}\tc={} d={} t={}",
+                entry.commits, entry.dee, entry.tick
+            )
+            .unwrap();
+        }
+        text
+    }
+}
+
+/// 所有的词库文本快照，包括用户目录下及同步目录各子目录中的文件
+fn snapshot_files(dir: &Path, sync_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![dir.to_path_buf()];
+    if let Ok(entries) = fs::read_dir(sync_dir) {
+        dirs.extend(entries.flatten().map(|x| x.path()).filter(|x| x.is_dir()));
+    }
+
+    let mut files = vec![];
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        files.extend(entries.flatten().map(|x| x.path()).filter(|x| {
+            x.is_file()
+                && x.file_name()
+                    .is_some_and(|x| x.to_string_lossy().ends_with(SNAPSHOT_EXTENSION))
+        }));
+    }
+    files.sort();
+    files
+}
+
+/// 输入法自带的 rime_dict_manager，与输入法的可执行文件位于同一目录
+fn dict_manager(config: &IMUpdateConfig) -> Option<PathBuf> {
+    let name = if cfg!(windows) {
+        "rime_dict_manager.exe"
+    } else {
+        "rime_dict_manager"
+    };
+    let path = config.exe.as_ref()?.parent()?.join(name);
+    path.is_file().then_some(path)
+}
+
+/// 使用输入法自带的 rime_dict_manager 将用户目录中的词库导出为文本快照，
+/// 没有该工具或导出失败时（如输入法正在使用词库）只能使用已有的快照
+pub fn backup_with_dict_manager(config: &IMUpdateConfig) {
+    let dir = &config.user_dir;
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            file_name.strip_suffix(".userdb").map(str::to_string)
+        })
+        .collect();
+    if names.is_empty() {
+        return;
+    }
+
+    let Some(dict_manager) = dict_manager(config) else {
+        println!("没有在输入法的安装目录中找到 rime_dict_manager，使用已有的词库快照");
+        return;
+    };
+    for name in names {
+        let result = Command::new(&dict_manager)
+            .arg("--backup")
+            .arg(&name)
+            .current_dir(dir)
+            .output();
+        match result {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => println!(
+                "导出词库 {name} 失败，使用已有的快照：{}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(error) => println!("执行 {} 失败：{error}", dict_manager.display()),
+        }
+    }
+}
+
+/// 读取 Rime 用户目录及同步目录中的所有词库快照，同一词库的多个快照合并为一个
+pub fn read_userdbs(dir: &Path, sync_dir: &Path) -> BTreeMap<String, UserDb> {
+    let mut userdbs: BTreeMap<String, UserDb> = BTreeMap::new();
+    for path in snapshot_files(dir, sync_dir) {
+        let userdb = match fs::read_to_string(&path).and_then(|x| UserDb::parse(&x)) {
+            Ok(userdb) if userdb.is_empty() => continue,
+            Ok(userdb) => userdb,
+            Err(error) => {
+                println!("跳过 {}：{error}", path.display());
+                continue;
+            }
+        };
+
+        match userdbs.get_mut(&userdb.name) {
+            Some(merged) => merged.merge(&userdb),
+            None => {
+                userdbs.insert(userdb.name.clone(), userdb);
+            }
+        }
+    }
+    userdbs
+}
+
+/// 将合并后的词库写入同步目录中，返回写入的文件
+pub fn write_userdbs(
+    sync_dir: &Path,
+    userdbs: &BTreeMap<String, UserDb>,
+) -> io::Result<Vec<PathBuf>> {
+    let merge_dir = sync_dir.join(MERGE_DIR);
+    fs::create_dir_all(&merge_dir)?;
+
+    let mut files = vec![];
+    for userdb in userdbs.values() {
+        let path = merge_dir.join(format!("{}{SNAPSHOT_EXTENSION}", userdb.name));
+        fs::write(&path, userdb.to_text())?;
+        files.push(path);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SNAPSHOT: &str = "# Rime user dictionary
+#@/db_name\tsbsrf
+#@/db_type\tuserdb
+#@/rime_version\t1.11.2
+#@/tick\t100
+sb\t声笔\tc=3 d=1.5 t=90
+sbsr\t声笔输入\tc=-1 d=0 t=100
+";
+
+    fn userdb(tick: u64, entries: &[(&str, &str)]) -> UserDb {
+        let mut content = format!("#@/db_name\tsbsrf\n#@/db_type\tuserdb\n#@/tick\t{tick}\n");
+        for (key, value) in entries {
+            content.push_str(&format!("{key}\t{value}\n"));
+        }
+        UserDb::parse(&content).unwrap()
+    }
+
+    #[test]
+    fn parse_and_to_text_round_trip() {
+        let db = UserDb::parse(SNAPSHOT).unwrap();
+        assert_eq!(db.name, "sbsrf");
+        assert_eq!(db.tick, 100);
+        assert_eq!(db.len(), 2);
+        let entry = db.entries["sb\t声笔"];
+        assert_eq!((entry.commits, entry.dee, entry.tick), (3, 1.5, 90));
+        assert_eq!(db.to_text(), SNAPSHOT);
+    }
+
+    #[test]
+    fn parse_legacy_name_and_invalid_snapshot() {
+        let db = UserDb::parse("#@/db_name\tsbsrf.userdb\n#@/db_type\tuserdb\n").unwrap();
+        assert_eq!(db.name, "sbsrf");
+        assert!(db.is_empty());
+
+        assert!(UserDb::parse("#@/db_name\tsbsrf\n#@/db_type\ttabledb\n").is_err());
+        assert!(UserDb::parse("#@/db_type\tuserdb\n").is_err());
+    }
+
+    #[test]
+    fn merge_keeps_entries_from_both_sides() {
+        let mut ours = userdb(10, &[("sb\t声笔", "c=1 d=1 t=10")]);
+        let theirs = userdb(10, &[("sf\t声笔法", "c=2 d=2 t=10")]);
+        ours.merge(&theirs);
+
+        assert_eq!(ours.len(), 2);
+        let entry = ours.entries["sb\t声笔"];
+        assert_eq!((entry.commits, entry.dee, entry.tick), (1, 1.0, 10));
+        let entry = ours.entries["sf\t声笔法"];
+        assert_eq!((entry.commits, entry.dee, entry.tick), (2, 2.0, 10));
+    }
+
+    #[test]
+    fn merge_prefers_larger_commits() {
+        let mut ours = userdb(
+            10,
+            &[("sb\t声笔", "c=3 d=1 t=10"), ("sf\t声笔法", "c=4 d=1 t=10")],
+        );
+        let theirs = userdb(
+            10,
+            &[
+                ("sb\t声笔", "c=-5 d=1 t=10"),
+                ("sf\t声笔法", "c=2 d=1 t=10"),
+            ],
+        );
+        ours.merge(&theirs);
+
+        // 绝对值较大的负数表示词条在另一台设备上被删除
+        assert_eq!(ours.entries["sb\t声笔"].commits, -5);
+        assert_eq!(ours.entries["sf\t声笔法"].commits, 4);
+    }
+
+    #[test]
+    fn merge_decays_dee_to_each_tick() {
+        let mut ours = userdb(
+            100,
+            &[
+                ("sb\t声笔", "c=1 d=1 t=100"),
+                ("sf\t声笔法", "c=1 d=1 t=100"),
+            ],
+        );
+        let theirs = userdb(
+            300,
+            &[
+                ("sb\t声笔", "c=1 d=3 t=100"),
+                ("sf\t声笔法", "c=1 d=2 t=100"),
+            ],
+        );
+        ours.merge(&theirs);
+
+        // 对方的词条在其词库中已经过了 200 个 tick，权重衰减为原来的 1/e
+        let decay = (-1.0f64).exp();
+        assert_eq!(ours.tick, 300);
+        let entry = ours.entries["sb\t声笔"];
+        assert!((entry.dee - 3.0 * decay).abs() < 1e-9);
+        assert_eq!(entry.tick, 300);
+        let entry = ours.entries["sf\t声笔法"];
+        assert_eq!((entry.dee, entry.tick), (1.0, 300));
+    }
+
+    #[test]
+    fn decayed() {
+        let entry = Entry::parse("c=1 d=1.5 t=100");
+        assert!((entry.decayed(300) - 1.5 * (-1.0f64).exp()).abs() < 1e-9);
+        assert_eq!(entry.decayed(100), 1.5);
+        assert_eq!(entry.decayed(50), 1.5);
+    }
+}
@@ -1,15 +1,20 @@
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use rayon::prelude::*;
-use reqwest::Client;
-use std::collections::VecDeque;
-use std::env::consts::OS;
+use regex::Regex;
+use reqwest::{Certificate, Client, NoProxy, Proxy};
+use std::collections::{BTreeSet, VecDeque};
+use std::env::{self, consts::OS};
 use std::fs::{self, File};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
+use time::{format_description, OffsetDateTime};
 use zip::ZipArchive;
 
+use crate::{im::IMUpdateConfig, settings::NetworkSettings};
+
 pub fn copy_dir_contents<F>(from: &Path, to: &Path, callback: F) -> std::io::Result<()>
 where
     F: Fn(&PathBuf),
@@ -40,7 +45,32 @@ where
     Ok(())
 }
 
-pub async fn download_file<F>(url: String, file_path: &PathBuf, callback: F) -> reqwest::Result<()>
+/// 目录中所有文件的相对路径
+pub fn list_files(dir: &Path) -> BTreeSet<String> {
+    let mut files = BTreeSet::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else if let Ok(relative) = path.strip_prefix(dir) {
+                files.insert(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    files
+}
+
+pub async fn download_file<F>(
+    url: String,
+    file_path: &PathBuf,
+    callback: F,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
 where
     F: Fn(usize, u64),
 {
@@ -48,10 +78,13 @@ where
         fs::create_dir_all(file_path.parent().unwrap()).unwrap();
     }
     let mut file = File::create(file_path).unwrap();
-    let mut response = reqwest::get(url).await?;
-    let total = response.content_length().unwrap();
+    let mut response = http_client().get(url).send().await?.error_for_status()?;
+    let total = response.content_length().unwrap_or_default();
     callback(0, total);
-    while let Some(chunk) = response.chunk().await? {
+    while let Some(chunk) = tokio::time::timeout(read_timeout(), response.chunk())
+        .await
+        .map_err(|_| "读取数据超时")??
+    {
         file.write_all(&chunk).unwrap();
         callback(chunk.len(), total);
     }
@@ -101,7 +134,7 @@ pub async fn upload_to_ios(
     device_host: &str,
     pb: &ProgressBar,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let client = Client::new();
+    let client = http_client();
     let mut stack = VecDeque::new();
     stack.push_back(file_path.to_path_buf());
 
@@ -119,9 +152,10 @@ pub async fn upload_to_ios(
                 pb.inc(1);
 
                 let mut buffer = Vec::new();
-                let mut file = File::open(entry_path.clone()).unwrap();
-                file.read_to_end(&mut buffer).unwrap();
+                let mut file = File::open(entry_path.clone())?;
+                file.read_to_end(&mut buffer)?;
 
+                // 语言模型等文件较大，不限制整个请求的时间，只依赖客户端的连接超时
                 let response = client
                     .post(format!(
                         "http://{device_host}/api/tus/Rime/{name}?override=true"
@@ -129,10 +163,9 @@ pub async fn upload_to_ios(
                     .header("Content-Type", "application/octet-stream")
                     .body(buffer)
                     .send()
-                    .await
-                    .unwrap();
+                    .await?;
                 if !response.status().is_success() {
-                    eprintln!("上传失败")
+                    return Err(format!("上传 {name} 失败：{}", response.status()).into());
                 }
             }
         }
@@ -143,14 +176,295 @@ pub async fn upload_to_ios(
     Ok(())
 }
 
-pub fn work_dir() -> PathBuf {
-    let home = if OS == "windows" {
-        std::env::var("USERPROFILE").unwrap()
+/// 工作目录的布局：设备配置、缓存及备份分别所在的目录
+struct WorkDirs {
+    config: PathBuf,
+    cache: PathBuf,
+    data: PathBuf,
+}
+
+static WORK_DIRS: OnceLock<WorkDirs> = OnceLock::new();
+
+impl WorkDirs {
+    /// 指定了工作目录时所有内容都放在该目录下，否则 Linux 下遵循 XDG 规范，其它平台使用 ~/.sbsrf-update
+    fn resolve(custom: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(dir) = custom {
+            return Ok(Self::single(dir));
+        }
+
+        let home = home_dir().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "无法确定用户主目录，请通过 --work-dir 参数或 SBSRF_UPDATE_HOME 环境变量指定工作目录",
+            )
+        })?;
+
+        if OS == "linux" {
+            let xdg = |var: &str, default: &str| {
+                std::env::var_os(var)
+                    .map(PathBuf::from)
+                    .filter(|path| path.is_absolute())
+                    .unwrap_or_else(|| home.join(default))
+                    .join("sbsrf-update")
+            };
+
+            return Ok(Self {
+                config: xdg("XDG_CONFIG_HOME", ".config"),
+                cache: xdg("XDG_CACHE_HOME", ".cache"),
+                data: xdg("XDG_DATA_HOME", ".local/share"),
+            });
+        }
+
+        Ok(Self::single(home.join(".sbsrf-update")))
+    }
+
+    fn single(dir: PathBuf) -> Self {
+        Self {
+            config: dir.clone(),
+            cache: dir.join("_cache"),
+            data: dir,
+        }
+    }
+}
+
+/// 初始化工作目录，需要在读取任何配置之前调用
+pub fn init_work_dir(custom: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let custom_specified = custom.is_some();
+    let dirs = WorkDirs::resolve(custom)?;
+    if !custom_specified {
+        migrate_legacy_work_dir(&dirs)?;
+    }
+
+    let _ = WORK_DIRS.set(dirs);
+    Ok(())
+}
+
+fn work_dirs() -> &'static WorkDirs {
+    WORK_DIRS.get_or_init(|| WorkDirs::resolve(None).unwrap())
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    let var = if OS == "windows" {
+        "USERPROFILE"
     } else {
-        std::env::var("HOME").unwrap()
+        "HOME"
     };
+    std::env::var_os(var)
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// 设备配置所在目录
+pub fn work_dir() -> PathBuf {
+    work_dirs().config.clone()
+}
+
+/// 下载文件及解压文件的缓存目录
+pub fn cache_dir() -> PathBuf {
+    work_dirs().cache.clone()
+}
+
+/// 备份所在目录，其下按设备名称划分
+pub fn data_dir() -> PathBuf {
+    work_dirs().data.clone()
+}
+
+/// 将旧版本的 ~/.sbsrf-update 迁移到新的目录布局下，仅在新布局与旧目录不一致且新配置目录还不存在时进行
+///
+/// 先读取所有设备配置，有无法解析的配置时不做任何修改；配置先写入临时目录，全部完成后才重命名为新的配置目录，
+/// 中途失败时下次启动会继续迁移。旧目录中只删除已经移走的文件，最后删除变为空的目录
+fn migrate_legacy_work_dir(dirs: &WorkDirs) -> Result<(), Box<dyn std::error::Error>> {
+    let legacy = match home_dir() {
+        Some(home) => home.join(".sbsrf-update"),
+        None => return Ok(()),
+    };
+    if !legacy.is_dir() || legacy == dirs.config || dirs.config.exists() {
+        return Ok(());
+    }
+
+    let mut configs = vec![];
+    for entry in fs::read_dir(&legacy)? {
+        let path = entry?.path().join("config.toml");
+        if path.is_file() {
+            let config: IMUpdateConfig = toml::from_str(&fs::read_to_string(&path)?)
+                .map_err(|error| format!("无法迁移 {}：{error}", path.display()))?;
+            configs.push((path, config));
+        }
+    }
 
-    PathBuf::from(home).join(".sbsrf-update")
+    println!(
+        "迁移工作目录 {} 到 {}",
+        legacy.display(),
+        dirs.config.display()
+    );
+    let staging = dirs.config.with_extension("migrating");
+    fs::create_dir_all(&staging)?;
+    for entry in fs::read_dir(&legacy)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let path = entry.path();
+        if name == "_cache" {
+            move_dir(&path, &dirs.cache)?;
+        } else if !path.is_dir() {
+            move_dir(&path, &staging.join(&name))?;
+        } else {
+            for child in fs::read_dir(&path)? {
+                let child = child?;
+                let child_name = child.file_name();
+                if child_name == "backups" {
+                    move_dir(&child.path(), &dirs.data.join(&name).join("backups"))?;
+                } else if child_name != "config.toml" {
+                    move_dir(&child.path(), &staging.join(&name).join(&child_name))?;
+                }
+            }
+        }
+    }
+
+    for (path, config) in &mut configs {
+        let name = path.parent().and_then(Path::file_name).unwrap_or_default();
+        config.update_dir = dirs.config.join(name);
+        fs::create_dir_all(staging.join(name))?;
+        fs::write(
+            staging.join(name).join("config.toml"),
+            toml::to_string(&config)?,
+        )?;
+    }
+    fs::rename(&staging, &dirs.config)?;
+
+    for (path, _) in &configs {
+        fs::remove_file(path)?;
+    }
+    remove_empty_dirs(&legacy);
+    Ok(())
+}
+
+/// 删除目录及其下所有的空目录，目录中还有文件时保留
+fn remove_empty_dirs(dir: &Path) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        if entry.file_type().is_ok_and(|x| x.is_dir()) {
+            remove_empty_dirs(&entry.path());
+        }
+    }
+    let _ = fs::remove_dir(dir);
+}
+
+/// 移动文件或目录，跨文件系统无法直接重命名时复制后再删除
+fn move_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    if from.is_dir() {
+        copy_dir_contents(from, to, |_| {})?;
+        fs::remove_dir_all(from)
+    } else {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    }
+}
+
+static HTTP_CLIENT: OnceLock<Client> = OnceLock::new();
+static OFFLINE: OnceLock<bool> = OnceLock::new();
+static READ_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// 局域网及本机地址，仓输入法等设备通过这些地址连接，不经过代理
+const LAN_ADDRESSES: &str = "localhost,.local,127.0.0.0/8,::1,10.0.0.0/8,172.16.0.0/12,192.168.0.0/16,169.254.0.0/16,fc00::/7,fe80::/10";
+
+/// 不使用代理的地址，环境变量 NO_PROXY 中的地址及局域网地址
+fn no_proxy() -> Option<NoProxy> {
+    let from_env = env::var("NO_PROXY")
+        .or_else(|_| env::var("no_proxy"))
+        .unwrap_or_default();
+    let list: Vec<&str> = from_env
+        .split(',')
+        .chain(LAN_ADDRESSES.split(','))
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .collect();
+    NoProxy::from_string(&list.join(","))
+}
+
+/// 根据网络设置初始化所有网络请求共用的客户端，`proxy` 为命令行中指定的代理，优先于设置文件
+pub fn init_http_client(
+    settings: &NetworkSettings,
+    proxy: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut builder = Client::builder()
+        .user_agent("Sbsrf-Update-App")
+        .connect_timeout(Duration::from_secs(settings.connect_timeout));
+
+    if let Some(proxy) = proxy.or(settings.proxy.as_ref()) {
+        let proxy = Proxy::all(proxy)
+            .map_err(|error| format!("代理地址 {proxy} 无效: {error}"))?
+            .no_proxy(no_proxy());
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(ca_cert) = &settings.ca_cert {
+        let pem = fs::read(ca_cert)
+            .map_err(|error| format!("读取 CA 证书 {} 失败: {error}", ca_cert.display()))?;
+        let certificate = Certificate::from_pem(&pem)
+            .map_err(|error| format!("CA 证书 {} 无效: {error}", ca_cert.display()))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+
+    let _ = HTTP_CLIENT.set(builder.build()?);
+    let _ = READ_TIMEOUT.set(Duration::from_secs(settings.read_timeout));
+    Ok(())
+}
+
+/// 离线模式下不发起网络请求，只使用缓存
+pub fn set_offline(offline: bool) {
+    let _ = OFFLINE.set(offline);
+}
+
+pub fn offline() -> bool {
+    OFFLINE.get().copied().unwrap_or_default()
+}
+
+pub fn http_client() -> Client {
+    HTTP_CLIENT.get_or_init(Client::new).clone()
+}
+
+/// 单次读取的超时时间，下载时按数据块计算，上传不限制，其它请求按整个请求计算
+pub fn read_timeout() -> Duration {
+    *READ_TIMEOUT.get_or_init(|| Duration::from_secs(NetworkSettings::default().read_timeout))
+}
+
+/// 将通配符转换为正则表达式，`**` 匹配任意字符，`*` 及 `?` 不匹配路径分隔符
+pub fn glob_to_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut expr = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                expr.push_str(".*");
+            }
+            '*' => expr.push_str("[^/]*"),
+            '?' => expr.push_str("[^/]"),
+            _ => expr.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expr.push('$');
+    Regex::new(&expr)
+}
+
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    glob_to_regex(pattern).is_ok_and(|regex| regex.is_match(text))
+}
+
+/// 以 UTC 显示的时间，如 2024-04-12 08:30:00 UTC
+pub fn format_time(time: SystemTime) -> String {
+    let format = format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second] UTC")
+        .expect("时间格式无效");
+    OffsetDateTime::from(time)
+        .format(&format)
+        .unwrap_or_default()
 }
 
 pub fn get_bar_style() -> ProgressStyle {
@@ -166,64 +480,39 @@ pub fn get_spinner_style() -> ProgressStyle {
         .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
 }
 
-pub fn ensure_max_backups(backup_path: &PathBuf, max_backups: i32) {
-    if !backup_path.exists() {
-        fs::create_dir_all(backup_path).unwrap();
-        return;
+/// 将发布文件下载到缓存目录，已缓存时直接使用，离线模式下缓存中没有时返回 None
+pub async fn download_to_cache(name: &str, url: &str, m: &MultiProgress) -> Option<PathBuf> {
+    let file_path = cache_dir().join(name);
+    if file_path.exists() {
+        return Some(file_path);
     }
 
-    let backups = fs::read_dir(backup_path).unwrap().filter_map(Result::ok);
-    let count = backups.count();
-    if count >= max_backups as usize {
-        let backups = fs::read_dir(backup_path).unwrap().filter_map(Result::ok);
-        let mut backup_items: Vec<_> = backups.collect();
-        backup_items.sort_by_key(|x| x.file_name());
-        backup_items
-            .iter()
-            .take(count + 1 - max_backups as usize)
-            .for_each(|backup| fs::remove_dir_all(backup.path()).unwrap())
+    if offline() {
+        println!("离线模式下缓存中没有 {name}，略过");
+        return None;
     }
-}
 
-#[cfg(target_os = "macos")]
-pub fn grep(keyword: &str) -> Result<String, Box<dyn std::error::Error>> {
-    use std::process::Stdio;
-
-    let ps = Command::new("ps")
-        .arg("aux")
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("ps 命令失败");
-
-    let grep = Command::new("grep")
-        .arg(keyword)
-        .stdin(ps.stdout.unwrap())
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("grep 命令失败");
-
-    let tr = Command::new("tr")
-        .args(["-s", " "])
-        .stdin(grep.stdout.unwrap())
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("tr 命令失败");
-
-    let output = Command::new("cut")
-        .args(["-d", " ", "-f", "11-"])
-        .stdin(tr.stdout.unwrap())
-        .output()
-        .expect("cut 命令失败");
-
-    let output_str = String::from_utf8(output.stdout).unwrap();
-    if output_str.trim() == "" {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("{keyword} not found"),
-        )));
+    // 下载文件
+    let pb = m.add(ProgressBar::new(100));
+    pb.set_prefix(format!("下载 {name}"));
+    pb.set_style(get_bar_style());
+
+    let result = download_file(url.to_string(), &file_path, |len, total| {
+        pb.set_length(total);
+        pb.inc(len as u64);
+    })
+    .await;
+    pb.finish();
+
+    match result {
+        Ok(_) => Some(file_path),
+        Err(error) => {
+            println!("下载文件{name}失败: {error}");
+            // 不完整的文件会被当作已缓存，删除以便下次重新下载
+            let _ = fs::remove_file(&file_path);
+            None
+        }
     }
-
-    Ok(output_str.trim().to_string())
 }
 
 pub async fn download_and_install(
@@ -232,26 +521,9 @@ pub async fn download_and_install(
     url: String,
     m: MultiProgress,
 ) {
-    let cache_dir = work_dir().join("_cache");
-    let file_path = cache_dir.join(&name);
-
-    if !file_path.exists() {
-        // 下载文件
-        let pb = m.add(ProgressBar::new(100));
-        pb.set_prefix(format!("下载 {}", &name));
-        pb.set_style(get_bar_style());
-
-        if let Err(error) = download_file(url.to_string(), &file_path, |len, total| {
-            pb.set_length(total);
-            pb.inc(len as u64);
-        })
-        .await
-        {
-            println!("下载文件{}失败: {error}", &name);
-        }
-
-        pb.finish();
-    }
+    let Some(file_path) = download_to_cache(&name, &url, &m).await else {
+        return;
+    };
 
     // 解压
     let pb = m.add(ProgressBar::new_spinner());
@@ -268,6 +540,14 @@ pub fn open(target: PathBuf) {
         .expect("打开文件失败");
 }
 
+#[cfg(target_os = "linux")]
+pub fn open(target: PathBuf) {
+    Command::new("xdg-open")
+        .arg(target.as_os_str())
+        .status()
+        .expect("打开文件失败");
+}
+
 #[cfg(target_os = "windows")]
 pub fn open(target: PathBuf) {
     Command::new("cmd")
@@ -275,3 +555,48 @@ pub fn open(target: PathBuf) {
         .status()
         .expect("打开文件失败");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_star() {
+        assert!(glob_match("*.yaml", "sbsrf.schema.yaml"));
+        assert!(glob_match("*.yaml", ".yaml"));
+        assert!(!glob_match("*.yaml", "build/sbsrf.schema.yaml"));
+        assert!(!glob_match("*.yaml", "sbsrf.schema.yaml.bak"));
+        assert!(glob_match("sbsrf*", "sbsrf-big.zip"));
+    }
+
+    #[test]
+    fn glob_double_star() {
+        assert!(glob_match("**.userdb", "sbsrf.userdb"));
+        assert!(glob_match("**.userdb", "sync/mac/sbsrf.userdb"));
+        assert!(glob_match("build/**", "build/opencc/emoji.txt"));
+        assert!(!glob_match("build/**", "sbsrf.schema.yaml"));
+    }
+
+    #[test]
+    fn glob_question_mark() {
+        assert!(glob_match("sbsrf?.zip", "sbsrf1.zip"));
+        assert!(!glob_match("sbsrf?.zip", "sbsrf.zip"));
+        assert!(!glob_match("sbsrf?.zip", "sbsrf12.zip"));
+        assert!(!glob_match("a?b", "a/b"));
+    }
+
+    #[test]
+    fn glob_escapes_metacharacters() {
+        assert!(glob_match("sbsrf.zip", "sbsrf.zip"));
+        assert!(!glob_match("sbsrf.zip", "sbsrf-zip"));
+        assert!(glob_match("c++.txt", "c++.txt"));
+        assert!(!glob_match("c++.txt", "cc.txt"));
+        assert!(glob_match("lua/(rime).lua", "lua/(rime).lua"));
+        assert!(glob_match("[a]{1}^$|\\.txt", "[a]{1}^$|\\.txt"));
+        assert!(!glob_match("[a].txt", "a.txt"));
+        assert_eq!(
+            glob_to_regex("a.b+(c)*").unwrap().as_str(),
+            r"^a\.b\+\(c\)[^/]*$"
+        );
+    }
+}
@@ -0,0 +1,515 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use console::style;
+use indicatif::MultiProgress;
+use yaml_rust2::{Yaml, YamlLoader};
+use zip::ZipArchive;
+
+use crate::{
+    custom::device_patches,
+    im::{select_assets, IMUpdateConfig},
+    plan::fetch_asset,
+    release::Release,
+    utils::{download_to_cache, list_files},
+};
+
+/// 检查出的问题，能确定位置时带有行号
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub file: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(file: &str, line: Option<usize>, message: String) -> Self {
+        Self {
+            file: file.to_string(),
+            line,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}: {}", self.file, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+/// 需要检查的 YAML 文件
+struct YamlFile {
+    /// 显示在问题中的位置，如 sbsrf.zip:sbsrf.schema.yaml
+    origin: String,
+    content: String,
+}
+
+impl YamlFile {
+    fn new(origin: String, content: String) -> Self {
+        // 词典的词条不是 YAML，只检查 ... 之前的文件头
+        let content = match content.find("\n...") {
+            Some(end) => content[..end].to_string(),
+            None => content,
+        };
+        Self { origin, content }
+    }
+
+    fn load(&self) -> Result<Yaml, Problem> {
+        match YamlLoader::load_from_str(&self.content) {
+            Ok(docs) => Ok(docs.into_iter().next().unwrap_or(Yaml::Null)),
+            Err(error) => Err(Problem::new(
+                &self.origin,
+                Some(error.marker().line()),
+                format!("YAML 格式错误，{}", error.info()),
+            )),
+        }
+    }
+
+    /// 第一个包含 `text` 的行号
+    fn line_of(&self, text: &str) -> Option<usize> {
+        self.content
+            .lines()
+            .position(|x| x.contains(text))
+            .map(|x| x + 1)
+    }
+}
+
+/// 静态检查 Rime 的配置：YAML 格式，default.yaml 中 schema_list 引用的方案，
+/// 以及方案引用的词典是否存在
+#[derive(Default)]
+pub struct Validator {
+    /// 需要检查的文件，键为相对于用户目录的路径，后加入的同名文件覆盖之前的
+    files: BTreeMap<String, YamlFile>,
+    /// 不检查但可以被引用的文件，如用户目录及输入法共享目录中的文件
+    available: BTreeMap<String, PathBuf>,
+}
+
+impl Validator {
+    /// 加入目录中的文件，`check` 返回 true 的 YAML 文件需要检查
+    fn add_dir(&mut self, dir: &Path, check: fn(&str) -> bool) {
+        for name in list_files(dir) {
+            let path = dir.join(&name);
+            if name.ends_with(".yaml") && check(&name) {
+                match fs::read_to_string(&path) {
+                    Ok(content) => self.add_content(&name, path.display().to_string(), content),
+                    Err(_) => {
+                        self.available.insert(name, path);
+                    }
+                }
+            } else {
+                self.available.insert(name, path);
+            }
+        }
+    }
+
+    /// 加入发布文件中的所有 YAML 文件
+    fn add_zip(&mut self, path: &Path) -> io::Result<()> {
+        let zip_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let Some(name) = file.enclosed_name() else {
+                continue;
+            };
+            let name = name.to_string_lossy().replace('\\', "/");
+            if file.is_dir() || !name.ends_with(".yaml") {
+                continue;
+            }
+
+            let mut content = vec![];
+            file.read_to_end(&mut content)?;
+            let content = String::from_utf8_lossy(&content).to_string();
+            self.add_content(&name, format!("{zip_name}:{name}"), content);
+        }
+        Ok(())
+    }
+
+    fn add_content(&mut self, name: &str, origin: String, content: String) {
+        self.available.remove(name);
+        self.files
+            .insert(name.to_string(), YamlFile::new(origin, content));
+    }
+
+    /// 加入设备启用的补丁集中的文件，它们更新后会覆盖用户目录中的同名文件
+    fn add_patches(&mut self, config: &IMUpdateConfig) -> Option<Problem> {
+        let patches = match device_patches(config) {
+            Ok(patches) => patches,
+            Err(error) => return Some(Problem::new("_custom", None, error.to_string())),
+        };
+        for (name, path) in patches {
+            match fs::read_to_string(&path) {
+                Ok(content) => self.add_content(&name, path.display().to_string(), content),
+                Err(error) => {
+                    return Some(Problem::new(
+                        &path.display().to_string(),
+                        None,
+                        error.to_string(),
+                    ))
+                }
+            }
+        }
+        None
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.files.contains_key(name) || self.available.contains_key(name)
+    }
+
+    /// 需要的文件不在检查范围内时从可引用的文件中读取，如发布中没有 default.yaml 时使用用户目录中的
+    fn file(&self, name: &str) -> Option<YamlFile> {
+        if let Some(file) = self.files.get(name) {
+            return Some(YamlFile::new(file.origin.clone(), file.content.clone()));
+        }
+        let path = self.available.get(name)?;
+        let content = fs::read_to_string(path).ok()?;
+        Some(YamlFile::new(path.display().to_string(), content))
+    }
+
+    pub fn check(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+        let mut docs = BTreeMap::new();
+        for (name, file) in &self.files {
+            match file.load() {
+                Ok(doc) => {
+                    docs.insert(name.as_str(), doc);
+                }
+                Err(problem) => problems.push(problem),
+            }
+        }
+
+        problems.extend(self.check_schema_list());
+        for (name, doc) in &docs {
+            if let Some(schema_id) = name.strip_suffix(".schema.yaml") {
+                problems.extend(self.check_schema(schema_id));
+            } else if name.ends_with(".dict.yaml") {
+                problems.extend(self.check_dict(name, doc));
+            }
+        }
+        problems.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+        problems
+    }
+
+    /// 读取文件及其补丁，任一文件格式错误时返回 None，错误已在检查格式时报告
+    fn load_with_patch(&self, name: &str, patch_name: &str) -> Option<(YamlFile, Yaml, Patch)> {
+        let file = self.file(name)?;
+        let doc = file.load().ok()?;
+        let patch = match self.file(patch_name) {
+            Some(patch_file) => {
+                let doc = patch_file.load().ok()?;
+                Patch {
+                    file: Some(patch_file),
+                    doc: doc["patch"].clone(),
+                }
+            }
+            None => Patch {
+                file: None,
+                doc: Yaml::BadValue,
+            },
+        };
+        Some((file, doc, patch))
+    }
+
+    /// 生效的 schema_list 中的方案，补丁中的 schema_list 替换 default.yaml 中的
+    fn schema_list(&self) -> Vec<Reference> {
+        let Some((file, doc, patch)) = self.load_with_patch("default.yaml", "default.custom.yaml")
+        else {
+            return vec![];
+        };
+
+        let mut entries = vec![];
+        match patch.get("schema_list") {
+            Some(list) => entries.extend(list_of(list).map(|x| (x, patch.file()))),
+            None => entries.extend(list_of(&doc["schema_list"]).map(|x| (x, &file))),
+        }
+        if let Some(list) = patch.get("schema_list/+") {
+            entries.extend(list_of(list).map(|x| (x, patch.file())));
+        }
+        entries
+            .into_iter()
+            .filter_map(|(entry, source)| Reference::new(entry["schema"].as_str()?, source))
+            .collect()
+    }
+
+    /// 方案的 schema/dependencies 中依赖的方案
+    fn dependencies(&self, schema_id: &str) -> Vec<Reference> {
+        let Some((file, doc, patch)) = self.load_with_patch(
+            &format!("{schema_id}.schema.yaml"),
+            &format!("{schema_id}.custom.yaml"),
+        ) else {
+            return vec![];
+        };
+
+        let (list, source) = match patch.get("schema/dependencies") {
+            Some(list) => (list, patch.file()),
+            None => (&doc["schema"]["dependencies"], &file),
+        };
+        list_of(list)
+            .filter_map(|x| Reference::new(x.as_str()?, source))
+            .collect()
+    }
+
+    /// 部署时会编译的方案，即 schema_list 中的方案及其直接或间接依赖的方案
+    fn enabled_schemas(&self) -> BTreeSet<String> {
+        let mut enabled = BTreeSet::new();
+        let mut pending: Vec<String> = self.schema_list().into_iter().map(|x| x.id).collect();
+        while let Some(schema_id) = pending.pop() {
+            if enabled.insert(schema_id.clone()) {
+                pending.extend(self.dependencies(&schema_id).into_iter().map(|x| x.id));
+            }
+        }
+        enabled
+    }
+
+    /// schema_list 中引用的方案都应存在
+    fn check_schema_list(&self) -> Vec<Problem> {
+        self.schema_list()
+            .into_iter()
+            .filter(|x| !self.exists(&format!("{}.schema.yaml", x.id)))
+            .map(|x| x.problem(format!("schema_list 中的方案 {} 不存在", x.id)))
+            .collect()
+    }
+
+    /// 方案依赖的方案及各组件引用的词典都应存在
+    fn check_schema(&self, schema_id: &str) -> Vec<Problem> {
+        let Some((file, doc, patch)) = self.load_with_patch(
+            &format!("{schema_id}.schema.yaml"),
+            &format!("{schema_id}.custom.yaml"),
+        ) else {
+            return vec![];
+        };
+
+        let mut problems: Vec<Problem> = self
+            .dependencies(schema_id)
+            .into_iter()
+            .filter(|x| !self.exists(&format!("{}.schema.yaml", x.id)))
+            .map(|x| x.problem(format!("依赖的方案 {} 不存在", x.id)))
+            .collect();
+
+        // 包含 dictionary 的组件，如 translator、reverse_lookup，补丁中可能新增组件
+        let mut components: Vec<String> = doc
+            .as_hash()
+            .into_iter()
+            .flatten()
+            .filter(|(_, value)| !value["dictionary"].is_badvalue())
+            .filter_map(|(key, _)| key.as_str().map(str::to_string))
+            .collect();
+        for key in patch.keys() {
+            if let Some(component) = key.strip_suffix("/dictionary") {
+                if !components.iter().any(|x| x == component) {
+                    components.push(component.to_string());
+                }
+            }
+        }
+
+        for component in components {
+            let path = format!("{component}/dictionary");
+            let (dictionary, source) = match patch.get(&path) {
+                Some(value) => (value, patch.file()),
+                None => (&doc[component.as_str()]["dictionary"], &file),
+            };
+            let Some(dictionary) = dictionary.as_str().filter(|x| !x.is_empty()) else {
+                continue;
+            };
+            if !self.dict_exists(dictionary) {
+                problems.push(Problem::new(
+                    &source.origin,
+                    source.line_of(&format!("dictionary: {dictionary}")),
+                    format!("{path} 引用的词典 {dictionary} 不存在"),
+                ));
+            }
+        }
+        problems
+    }
+
+    /// 词典导入的其它词典都应存在
+    fn check_dict(&self, name: &str, doc: &Yaml) -> Vec<Problem> {
+        let file = &self.files[name];
+        list_of(&doc["import_tables"])
+            .filter_map(Yaml::as_str)
+            .filter(|x| !self.dict_exists(x))
+            .map(|dictionary| {
+                Problem::new(
+                    &file.origin,
+                    file.line_of(dictionary),
+                    format!("import_tables 中的词典 {dictionary} 不存在"),
+                )
+            })
+            .collect()
+    }
+
+    /// 词典源文件或已编译的词典存在
+    fn dict_exists(&self, dictionary: &str) -> bool {
+        self.exists(&format!("{dictionary}.dict.yaml"))
+            || self.exists(&format!("build/{dictionary}.table.bin"))
+    }
+}
+
+/// 配置中对方案的引用及其位置
+struct Reference {
+    id: String,
+    origin: String,
+    line: Option<usize>,
+}
+
+impl Reference {
+    fn new(id: &str, source: &YamlFile) -> Option<Self> {
+        Some(Self {
+            id: id.to_string(),
+            origin: source.origin.clone(),
+            line: source.line_of(id),
+        })
+    }
+
+    fn problem(&self, message: String) -> Problem {
+        Problem::new(&self.origin, self.line, message)
+    }
+}
+
+/// 补丁文件中 `patch:` 下的内容
+struct Patch {
+    file: Option<YamlFile>,
+    doc: Yaml,
+}
+
+impl Patch {
+    fn file(&self) -> &YamlFile {
+        self.file.as_ref().expect("补丁中有内容时文件一定存在")
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.doc
+            .as_hash()
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, _)| key.as_str())
+            .collect()
+    }
+
+    /// 补丁对 `path` 的修改，支持 `a/b: 值` 及 `a: {b: 值}` 两种写法
+    fn get(&self, path: &str) -> Option<&Yaml> {
+        let parts: Vec<&str> = path.split('/').collect();
+        for i in (1..=parts.len()).rev() {
+            let mut value = &self.doc[parts[..i].join("/").as_str()];
+            for part in &parts[i..] {
+                value = &value[*part];
+            }
+            if !value.is_badvalue() {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+fn list_of(value: &Yaml) -> impl Iterator<Item = &Yaml> {
+    value.as_vec().into_iter().flatten()
+}
+
+/// 输入法自带的 Rime 共享数据目录，方案可以引用其中的词典
+fn shared_data_dir(config: &IMUpdateConfig) -> Option<PathBuf> {
+    let exe = config.exe.as_ref()?;
+    match config.name.as_str() {
+        "Squirrel" => Some(exe.parent()?.parent()?.join("SharedSupport")),
+        "Fcitx5" => Some(exe.parent()?.parent()?.join("share/rime-data")),
+        "Weasel" => Some(exe.parent()?.join("data")),
+        _ => None,
+    }
+}
+
+/// 获取设备选用的发布文件，`temp_dir` 为 None 时下载到缓存目录，之后安装时不再重复下载
+pub async fn fetch_assets(
+    release: &Release,
+    config: &IMUpdateConfig,
+    temp_dir: Option<&Path>,
+) -> Vec<PathBuf> {
+    let m = MultiProgress::new();
+    let mut files = vec![];
+    for selection in select_assets(release, config) {
+        if !selection.selected {
+            continue;
+        }
+
+        let asset = selection.asset;
+        let file = match temp_dir {
+            Some(dir) => fetch_asset(release, &asset.name, &asset.download_url, dir).await,
+            None => download_to_cache(&asset.name, &asset.download_url, &m).await,
+        };
+        files.extend(file);
+    }
+    files
+}
+
+/// 检查发布文件中的配置及用户目录中的补丁，`user_dir` 为 None 时不考虑用户目录中的文件
+pub fn validate_release(
+    config: &IMUpdateConfig,
+    assets: &[PathBuf],
+    user_dir: Option<&Path>,
+) -> Vec<Problem> {
+    let mut validator = Validator::default();
+    if let Some(dir) = shared_data_dir(config) {
+        validator.add_dir(&dir, |_| false);
+    }
+    if let Some(dir) = user_dir {
+        validator.add_dir(dir, |name| name.ends_with(".custom.yaml"));
+    }
+
+    let mut problems = vec![];
+    for path in assets {
+        if let Err(error) = validator.add_zip(path) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            problems.push(Problem::new(&name, None, format!("读取失败，{error}")));
+        }
+    }
+    problems.extend(validator.add_patches(config));
+    problems.extend(validator.check());
+    problems
+}
+
+/// 部署时会编译的方案，用于检查部署结果，不在 schema_list 中的方案不会被编译
+pub fn enabled_schemas(config: &IMUpdateConfig) -> BTreeSet<String> {
+    let mut validator = Validator::default();
+    if let Some(dir) = shared_data_dir(config) {
+        validator.add_dir(&dir, |_| false);
+    }
+    validator.add_dir(&config.user_dir, |_| false);
+    validator.enabled_schemas()
+}
+
+/// 检查用户目录中现有的所有配置，不包括子目录中的文件
+pub fn validate_user_dir(config: &IMUpdateConfig, user_dir: &Path) -> Vec<Problem> {
+    let mut validator = Validator::default();
+    if let Some(dir) = shared_data_dir(config) {
+        validator.add_dir(&dir, |_| false);
+    }
+    validator.add_dir(user_dir, |name| !name.contains('/'));
+
+    let mut problems = vec![];
+    problems.extend(validator.add_patches(config));
+    problems.extend(validator.check());
+    problems
+}
+
+/// 显示检查结果，没有问题时返回 true
+pub fn print_problems(problems: &[Problem]) -> bool {
+    if problems.is_empty() {
+        println!("{}", style("配置检查通过").green());
+        return true;
+    }
+
+    println!(
+        "{}",
+        style(format!("发现 {} 个配置问题：", problems.len())).red()
+    );
+    for problem in problems {
+        println!("  {} {problem}", style("x").red());
+    }
+    false
+}
@@ -1,10 +1,5 @@
 use std::{
-    env::consts::OS,
-    fs::{self, File},
-    io::copy,
-    path::{Path, PathBuf},
-    process::Command,
-    thread::sleep,
+    env::consts::OS, fs::File, io::copy, path::PathBuf, process::Command, thread::sleep,
     time::Duration,
 };
 
@@ -13,13 +8,18 @@ use tempfile::tempdir;
 use zip::ZipArchive;
 
 use crate::{
-    im::{check_file_item, IMUpdateConfig, InputMethod},
-    utils::{
-        copy_dir_contents, download_and_install, download_file, ensure_max_backups, get_bar_style,
-        get_spinner_style, open, work_dir,
-    },
+    backup::{backup_user_dir, restore_user_dir, BackupReason, Snapshot},
+    custom::apply_to_user_dir,
+    im::{select_assets, AssetRules, IMUpdateConfig, InputMethod},
+    process::find_process,
+    release::{Channel, ReleaseSource},
+    retention::Retention,
+    utils::{cache_dir, download_and_install, download_file, get_bar_style, open, work_dir},
 };
 
+/// 小狼毫的算法服务进程
+pub const WEASEL_SERVER: &str = "WeaselServer.exe";
+
 #[derive(Debug)]
 pub struct Weasel {
     pub config: IMUpdateConfig,
@@ -39,45 +39,17 @@ impl Weasel {
             update_dir,
             max_backups: 1,
             sentence: false,
+            page_size: None,
             version: "20051203".to_string(),
+            channel: Channel::default(),
+            assets: AssetRules::default(),
+            source: ReleaseSource::default(),
+            retention: Retention::default(),
+            sync: false,
+            custom: vec![],
         }
     }
 
-    fn get_weasel_server_pid() -> i32 {
-        let output = Command::new("tasklist")
-            .args(["/FI", "IMAGENAME eq WeaselServer.exe"])
-            .output()
-            .expect("检测小狼毫程序运行状态失败");
-        let output_str = String::from_utf8_lossy(&output.stdout);
-
-        // 映像名称                       PID 会话名              会话#       内存使用
-        // ========================= ======== ================ =========== ============
-        // WeaselServer.exe              7528 Console                    1     10,068 K
-        if output_str.contains("WeaselServer.exe") {
-            let mut splited = output_str.split("\r\n");
-            if let Some(line) = splited.nth(3) {
-                let mut splited = line.split_ascii_whitespace();
-
-                let pid = splited.nth(1).unwrap_or("-1");
-                return pid.parse::<i32>().unwrap();
-            }
-        }
-
-        -1
-    }
-
-    fn get_weasel_exe(pid: i32) -> Option<PathBuf> {
-        let arg = format!("ProcessId={pid}");
-        let output = Command::new("wmic")
-            .args(["process", "where", &arg, "get", "ExecutablePath"])
-            .output()
-            .ok()?;
-
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        let mut splited = output_str.split("\r\n");
-        splited.nth(1).map(|path| PathBuf::from(path.trim()))
-    }
-
     fn toggle_weasel_server_state(&self, start: bool) {
         if let Some(exe_path) = self.config.clone().exe {
             let mut cmd = Command::new(exe_path.as_os_str());
@@ -89,7 +61,7 @@ impl Weasel {
     }
 
     pub fn running(&self) -> bool {
-        Weasel::get_weasel_server_pid() > 0
+        find_process(WEASEL_SERVER).is_some()
     }
 
     pub fn start(&self) {
@@ -104,7 +76,7 @@ impl Weasel {
 impl InputMethod for Weasel {
     async fn install(&self, name: &str, download_url: &str) {
         println!("准备安装小狼毫程序");
-        let file_path = work_dir().join("_cache").join(name);
+        let file_path = cache_dir().join(name);
         if !file_path.exists() {
             let pb = ProgressBar::new(100);
             pb.set_prefix(format!("下载 {}", name));
@@ -138,7 +110,7 @@ impl InputMethod for Weasel {
         while !self.running() {
             sleep(Duration::from_secs(1));
         }
-        if let Some(exe) = Weasel::get_weasel_exe(Weasel::get_weasel_server_pid()) {
+        if let Some(exe) = find_process(WEASEL_SERVER).and_then(|x| x.exe) {
             let mut config = self.config.clone();
             config.exe = Some(exe);
             config.write_config();
@@ -147,37 +119,11 @@ impl InputMethod for Weasel {
         }
     }
 
-    async fn backup(&self) {
-        let max_backups = self.config.max_backups;
-        if self.config.max_backups == 0 {
-            return;
-        }
-
-        let backup_path = self.config.update_dir.join("backups");
-        let target = backup_path.join(&self.config.version);
-        if target.exists() {
-            println!("当前版本已经备份在: {}，略过", target.display());
-            return;
-        }
-
-        ensure_max_backups(&backup_path, max_backups);
-
-        println!("备份当前版本到：{}", target.display());
-        let pb = ProgressBar::new_spinner();
-        pb.set_prefix(format!("备份当前版本 {}", &self.config.version));
-        pb.set_style(get_spinner_style());
-        let source = &self.config.user_dir;
-        if let Err(error) = copy_dir_contents(source, &target, |path| {
-            pb.set_message(format!("{}", path.display()));
-            pb.inc(1);
-        }) {
-            println!("备份当前版本失败：{error}");
-        }
-
-        pb.finish_with_message("完成");
+    async fn backup(&self, reason: BackupReason, label: Option<String>) -> Option<Snapshot> {
+        backup_user_dir(&self.config, reason, label)
     }
 
-    async fn restore(&self, version: &Path) {
+    async fn restore(&self, snapshot: &Snapshot, partial: bool) {
         let running = self.running();
         if running {
             println!("检测到小狼毫程序正在运行，暂时停止");
@@ -187,20 +133,7 @@ impl InputMethod for Weasel {
             }
         }
 
-        let from = version;
-        let to = &self.config.user_dir;
-        fs::remove_dir_all(to).unwrap();
-
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(get_spinner_style());
-        pb.set_prefix("还原");
-        if let Err(error) = copy_dir_contents(from, to, |entry| {
-            pb.set_message(format!("{}", entry.display()));
-            pb.inc(1);
-        }) {
-            println!("还原失败：{error}")
-        }
-        pb.finish_with_message("完成");
+        restore_user_dir(&self.config, snapshot, partial);
 
         if running {
             self.start();
@@ -214,7 +147,7 @@ impl InputMethod for Weasel {
         self.deploy();
     }
 
-    async fn update(&self, release: crate::release::Release) {
+    async fn update(&self, release: crate::release::Release) -> Result<Option<Snapshot>, String> {
         let running = self.running();
         if running {
             println!("检测到小狼毫程序正在运行，暂时停止");
@@ -225,16 +158,17 @@ impl InputMethod for Weasel {
         }
 
         println!("开始为本地的小狼毫更新声笔输入法...");
-        self.backup().await;
+        let snapshot = self.backup(BackupReason::Update, None).await;
 
         let m = MultiProgress::new();
         let mut tasks = vec![];
 
-        for asset in release.assets {
-            if !check_file_item(&asset.name, "weasel", self.config.sentence) {
+        for selection in select_assets(&release, &self.config) {
+            if !selection.selected {
                 continue;
             }
 
+            let asset = selection.asset;
             let name = asset.name;
             let download_url = asset.download_url;
             let target_dir = self.config.clone().user_dir;
@@ -261,8 +195,11 @@ impl InputMethod for Weasel {
             println!("小狼毫程序已恢复启动");
         }
 
+        apply_to_user_dir(&self.config);
+
         println!("文件更新完成，重新部署...");
         self.deploy();
+        Ok(snapshot)
     }
 
     fn deploy(&self) {
@@ -272,21 +209,23 @@ impl InputMethod for Weasel {
             cmd.spawn().unwrap();
         }
     }
+
+    fn sync(&self) {
+        if let Some(exe_path) = self.config.clone().exe {
+            let home = exe_path.parent().unwrap();
+            let mut cmd = Command::new(home.join("WeaselDeployer.exe").as_os_str());
+            cmd.arg("/sync").spawn().unwrap();
+        }
+    }
 }
 
 pub fn get_weasel() -> Result<Option<Weasel>, Box<dyn std::error::Error>> {
-    let pid = Weasel::get_weasel_server_pid();
-    if pid > 0 {
-        let update_dir = work_dir().join(OS);
-        let config_file = update_dir.join("config.toml");
-        if config_file.exists() {
-            // 配置文件存在，直接读取
-            let toml = fs::read_to_string(config_file)?;
-            let config: IMUpdateConfig = toml::from_str(&toml)?;
+    if let Some(process) = find_process(WEASEL_SERVER) {
+        if let Some(config) = IMUpdateConfig::new(OS)? {
             return Ok(Some(Weasel::new(config)));
         }
 
-        if let Some(exe) = Weasel::get_weasel_exe(pid) {
+        if let Some(exe) = process.exe {
             let mut config = Weasel::default_config();
             config.exe = Some(exe);
             config.write_config();